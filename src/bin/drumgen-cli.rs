@@ -0,0 +1,348 @@
+/*
+    Command-line tool for batch operations on DrumGen .hex templates.
+
+    Every command takes one or more files or glob patterns and edits the
+    templates in place (or writes to --output when a single file is given).
+    The process exits with a non-zero status if any file fails.
+*/
+use clap::{Args, Parser, Subcommand};
+use drumgen::midi::{MidiExportSettings, MidiLearnSettings, GrooveLearner};
+use drumgen::{DrumGen, DrumGenError, DrumGenSequence};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(name = "drumgen-cli", version, about = "Batch operations on NGEN DrumGen templates")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print each template in the text format
+    Info {
+        #[arg(required = true)]
+        files: Vec<String>,
+    },
+    /// Check that each file is a valid 192 byte template
+    Validate {
+        #[arg(required = true)]
+        files: Vec<String>,
+    },
+    /// Create empty templates
+    New {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Overwrite existing files
+        #[arg(long)]
+        force: bool,
+    },
+    /// Randomize the selected sequences
+    Randomize {
+        #[command(flatten)]
+        target: Target,
+        /// Lowest random value (0-10)
+        #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=10))]
+        min: u8,
+        /// Highest random value (0-10)
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(0..=10))]
+        max: u8,
+        /// Chance of each step being replaced (0-100)
+        #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u8).range(0..=100))]
+        prob: u8,
+        /// Seed for the random values (a new one is picked and printed if not given)
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Rotate the selected sequences (positive = left, negative = right)
+    Shift {
+        #[command(flatten)]
+        target: Target,
+        #[arg(long, allow_negative_numbers = true)]
+        by: i8,
+    },
+    /// Set every step of the selected sequences to 0
+    Clear {
+        #[command(flatten)]
+        target: Target,
+    },
+    /// Render templates to Standard MIDI Files (next to each input unless --output is given)
+    Midi {
+        #[arg(required = true)]
+        files: Vec<String>,
+        /// Write the MIDI file here (single input only)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Seed for rolling the step probabilities
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// Length of the pattern in bars
+        #[arg(long, default_value_t = 4)]
+        bars: u32,
+        /// MIDI notes for parts 1-4, comma separated
+        #[arg(long, value_delimiter = ',', default_values_t = drumgen::midi::GM_DRUM_NOTES, value_parser = clap::value_parser!(u8).range(0..=127))]
+        notes: Vec<u8>,
+        /// Tempo in BPM
+        #[arg(long, default_value_t = 120.0)]
+        tempo: f64,
+    },
+    /// Learn a template from MIDI drum loops
+    Learn {
+        /// MIDI files or glob patterns
+        #[arg(required = true)]
+        files: Vec<String>,
+        /// Template to write
+        #[arg(short, long)]
+        output: PathBuf,
+        /// MIDI notes for part 1, comma separated (default: kicks)
+        #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u8).range(0..=127))]
+        part1: Option<Vec<u8>>,
+        /// MIDI notes for part 2, comma separated (default: snares and clap)
+        #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u8).range(0..=127))]
+        part2: Option<Vec<u8>>,
+        /// MIDI notes for part 3, comma separated (default: closed and pedal hi-hat)
+        #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u8).range(0..=127))]
+        part3: Option<Vec<u8>>,
+        /// MIDI notes for part 4, comma separated (default: open hi-hat and cymbals)
+        #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u8).range(0..=127))]
+        part4: Option<Vec<u8>>,
+    },
+    /// Copy a part onto another part
+    CopyPart {
+        #[command(flatten)]
+        output: Output,
+        /// Source part (1-4)
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=4))]
+        from: u8,
+        /// Destination part (1-4)
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=4))]
+        to: u8,
+        /// Take the source part from this template instead of the edited one
+        #[arg(long)]
+        source: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+struct Target {
+    #[command(flatten)]
+    output: Output,
+    /// Only edit this part (1-4), all parts by default
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=4))]
+    part: Option<u8>,
+    /// Only edit this velocity layer (1-3), all layers by default
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=3))]
+    layer: Option<u8>,
+}
+
+#[derive(Args)]
+struct Output {
+    #[arg(required = true)]
+    files: Vec<String>,
+    /// Write the result here instead of editing in place (single input only)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+impl Target {
+    fn for_each_sequence(&self, template: &mut DrumGen, mut f: impl FnMut(&mut DrumGenSequence)) {
+        for (p, part) in template.parts.iter_mut().enumerate() {
+            if self.part.is_some_and(|selected| selected as usize != p + 1) {
+                continue;
+            }
+            for (l, sequence) in part.sequence.iter_mut().enumerate() {
+                if self.layer.is_some_and(|selected| selected as usize != l + 1) {
+                    continue;
+                }
+                f(sequence);
+            }
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let ok = match &cli.command {
+        Command::Info { files } => for_each_file(files, |path| {
+            let template = load(path)?;
+            let name = path.file_stem().map(|stem| stem.to_string_lossy());
+            println!("{}", template.to_text(name.as_deref()));
+            Ok(())
+        }),
+        Command::Validate { files } => for_each_file(files, |path| {
+            load(path)?;
+            match drumgen::filename::validate_file_name(path) {
+                Ok(()) => println!("{}: OK", path.display()),
+                Err(err) => println!("{}: OK, but NGEN won't load it: {}", path.display(), err),
+            }
+            Ok(())
+        }),
+        Command::New { files, force } => {
+            let mut ok = true;
+            for path in files {
+                ok &= report(if path.exists() && !force {
+                    Err(format!("{} already exists (use --force to overwrite)", path.display()))
+                } else {
+                    DrumGen::new().save_file(path).map_err(|err| describe(path, err))
+                });
+            }
+            ok
+        }
+        Command::Randomize { target, min, max, prob, seed } => {
+            let seed = seed.unwrap_or_else(rand::random);
+            println!("Randomizing with seed {}", seed);
+            edit_files(&target.output, |template| {
+                // Every file starts from the seed, so each one gets the same values.
+                let mut rng = StdRng::seed_from_u64(seed);
+                target.for_each_sequence(template, |sequence| sequence.randomize(*min, *max, *prob, &mut rng));
+                Ok(())
+            })
+        }
+        Command::Shift { target, by } => edit_files(&target.output, |template| {
+            target.for_each_sequence(template, |sequence| sequence.shift(*by));
+            Ok(())
+        }),
+        Command::Clear { target } => edit_files(&target.output, |template| {
+            target.for_each_sequence(template, |sequence| sequence.clear());
+            Ok(())
+        }),
+        Command::Midi { files, output, seed, bars, notes, tempo } => {
+            if notes.len() != 4 {
+                eprintln!("error: --notes needs 4 notes, got {}", notes.len());
+                return ExitCode::FAILURE;
+            }
+            let mut settings = MidiExportSettings { seed: *seed, bars: *bars, tempo: *tempo, ..Default::default() };
+            settings.notes.copy_from_slice(notes);
+            let output = Output { files: files.clone(), output: output.clone() };
+            if !check_single_output(&output) {
+                return ExitCode::FAILURE;
+            }
+            for_each_file(files, |path| {
+                let template = load(path)?;
+                let destination = output.output.clone().unwrap_or_else(|| path.with_extension("mid"));
+                template.save_midi_file(&destination, &settings).map_err(|err| describe(&destination, err))
+            })
+        }
+        Command::Learn { files, output, part1, part2, part3, part4 } => {
+            let mut settings = MidiLearnSettings::default();
+            for (notes, custom) in settings.notes.iter_mut().zip([part1, part2, part3, part4]) {
+                if let Some(custom) = custom {
+                    *notes = custom.clone();
+                }
+            }
+            let mut learner = GrooveLearner::new(settings);
+            let learned = for_each_file(files, |path| {
+                learner.add_file(path).map_err(|err| describe(path, err))
+            });
+            if !learned {
+                return ExitCode::FAILURE;
+            }
+            println!("Learned {} cycles of 32 steps", learner.cycles());
+            report(learner.template().save_file(output).map_err(|err| describe(output, err)))
+        }
+        Command::CopyPart { output, from, to, source } => {
+            let source = match source {
+                Some(path) => match load(path) {
+                    Ok(template) => Some(template),
+                    Err(err) => {
+                        eprintln!("error: {}", err);
+                        return ExitCode::FAILURE;
+                    }
+                },
+                None => None,
+            };
+            edit_files(output, |template| {
+                let part = source.as_ref().unwrap_or(template).parts[*from as usize - 1];
+                template.parts[*to as usize - 1] = part;
+                Ok(())
+            })
+        }
+    };
+    if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+// Print an error and return false if the result failed.
+fn report(result: Result<(), String>) -> bool {
+    match result {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            false
+        }
+    }
+}
+
+// Expand glob patterns, keeping plain paths as they are so missing files are reported.
+fn expand(patterns: &[String]) -> Result<Vec<PathBuf>, String> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        if !pattern.contains(['*', '?', '[']) {
+            paths.push(PathBuf::from(pattern));
+            continue;
+        }
+        let matches = glob::glob(pattern).map_err(|err| format!("invalid pattern {}: {}", pattern, err))?;
+        let before = paths.len();
+        for entry in matches {
+            paths.push(entry.map_err(|err| err.to_string())?);
+        }
+        if paths.len() == before {
+            return Err(format!("no files match {}", pattern));
+        }
+    }
+    Ok(paths)
+}
+
+fn for_each_file(patterns: &[String], mut f: impl FnMut(&Path) -> Result<(), String>) -> bool {
+    let paths = match expand(patterns) {
+        Ok(paths) => paths,
+        Err(err) => return report(Err(err)),
+    };
+    let mut ok = true;
+    for path in &paths {
+        ok &= report(f(path));
+    }
+    ok
+}
+
+// --output only makes sense for a single input file.
+fn check_single_output(output: &Output) -> bool {
+    if output.output.is_some() {
+        match expand(&output.files) {
+            Ok(paths) if paths.len() != 1 => {
+                return report(Err("--output needs exactly one input file".to_string()));
+            }
+            Err(err) => return report(Err(err)),
+            _ => {}
+        }
+    }
+    true
+}
+
+fn edit_files(output: &Output, mut f: impl FnMut(&mut DrumGen) -> Result<(), String>) -> bool {
+    if !check_single_output(output) {
+        return false;
+    }
+    for_each_file(&output.files, |path| {
+        let mut template = load(path)?;
+        f(&mut template)?;
+        let destination = output.output.as_deref().unwrap_or(path);
+        template.save_file(destination).map_err(|err| describe(destination, err))
+    })
+}
+
+fn load(path: &Path) -> Result<DrumGen, String> {
+    let mut template = DrumGen::new();
+    template.parse_file(path).map_err(|err| describe(path, err))?;
+    Ok(template)
+}
+
+fn describe(path: &Path, err: DrumGenError) -> String {
+    format!("{}: {}", path.display(), err)
+}
@@ -0,0 +1,209 @@
+/*
+    A template open in the editor.
+
+    Every tab holds one Document with its own file path, name, metadata,
+    undo history and the snapshot used to detect unsaved changes.
+*/
+use crate::history::History;
+use drumgen::filename::{suggest_file_name, suggest_name};
+use drumgen::{DrumGen, DrumGenError, DrumGenProject, TemplateMetadata};
+
+pub struct Document {
+    pub template: DrumGen,
+    pub name: String,
+    pub picked_path: Option<String>,
+    pub metadata: TemplateMetadata,
+    pub tags_input: String,
+    pub history: History<DrumGen>,
+    // Template as it was last opened or saved.
+    saved_template: DrumGen,
+}
+
+impl Document {
+    /// An empty template with the given name.
+    pub fn new(name: &str) -> Document {
+        Document {
+            template: DrumGen::new(),
+            name: name.to_string(),
+            picked_path: None,
+            metadata: TemplateMetadata::default(),
+            tags_input: String::new(),
+            history: History::default(),
+            saved_template: DrumGen::new(),
+        }
+    }
+
+    /// File name NGEN accepts for the template name, e.g. DGT_0001.HEX.
+    pub fn get_filename(&self) -> String {
+        suggest_file_name(&self.name)
+    }
+
+    /// True if the steps differ from the ones last opened or saved.
+    ///
+    /// Step locks are left out: Save writes a .hex file, which can't hold them,
+    /// so locking a step is not a change saving could keep.
+    pub fn is_dirty(&self) -> bool {
+        !same_steps(&self.template, &self.saved_template)
+    }
+
+    /// Name shown in the tab and window title, marked with * while dirty.
+    pub fn title(&self) -> String {
+        let marker = if self.is_dirty() { "*" } else { "" };
+        format!("{}{}", self.name, marker)
+    }
+
+    /// Replace the template with one that was just loaded, starting a new undo history.
+    pub fn load(&mut self, template: DrumGen, name: Option<String>, picked_path: Option<String>, metadata: TemplateMetadata) {
+        self.template = template;
+        self.saved_template = template;
+        self.picked_path = picked_path;
+        if let Some(name) = name {
+            self.name = name;
+        }
+        self.set_metadata(metadata);
+        self.history.clear();
+    }
+
+    pub fn set_metadata(&mut self, metadata: TemplateMetadata) {
+        self.tags_input = metadata.tags.join(", ");
+        self.metadata = metadata;
+    }
+
+    /// Open a .hex file, naming the document after it (made into a valid NGEN name).
+    pub fn open_file(&mut self, path: &std::path::Path) -> Result<(), DrumGenError> {
+        let mut template = DrumGen::new();
+        template.parse_file(path)?;
+        let name = path.file_stem().map(|name| suggest_name(&name.to_string_lossy()));
+        self.load(template, name, Some(path.display().to_string()), TemplateMetadata::default());
+        Ok(())
+    }
+
+    /// Save the template as a .hex file, which becomes the path used by the next Save.
+    pub fn save_file(&mut self, path: &str) -> Result<(), DrumGenError> {
+        self.template.save_file(path)?;
+        self.saved_template = self.template;
+        self.picked_path = Some(path.to_string());
+        Ok(())
+    }
+
+    /// The template with its metadata, taking the name and tags from the editor fields.
+    pub fn project(&mut self) -> DrumGenProject {
+        self.metadata.name = self.name.clone();
+        self.metadata.tags = self.tags_input
+            .split(',')
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        DrumGenProject {
+            metadata: self.metadata.clone(),
+            template: self.template,
+        }
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(template) = self.history.undo(&self.template) {
+            self.template = template;
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(template) = self.history.redo(&self.template) {
+            self.template = template;
+        }
+    }
+}
+
+// Whether two templates have the same steps, whatever their locks.
+fn same_steps(a: &DrumGen, b: &DrumGen) -> bool {
+    a.parts.iter().zip(b.parts.iter()).all(|(a, b)| {
+        a.sequence.iter().zip(b.sequence.iter()).all(|(a, b)| a.steps == b.steps)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use drumgen::StepMask;
+
+    #[test]
+    fn dirty_until_saved() {
+        let mut document = Document::new("DGT_0001");
+        assert!(!document.is_dirty());
+        document.template.parts[0].sequence[0].set_step(0, 10);
+        assert!(document.is_dirty());
+        assert_eq!(document.title(), "DGT_0001*");
+
+        let path = std::env::temp_dir().join(format!("drumgen_document_{}.hex", std::process::id()));
+        let path = path.display().to_string();
+        document.save_file(&path).unwrap();
+        assert!(!document.is_dirty());
+        assert_eq!(document.picked_path.as_deref(), Some(path.as_str()));
+
+        let mut reopened = Document::new("DGT_0002");
+        let result = reopened.open_file(std::path::Path::new(&path));
+        let _ = std::fs::remove_file(&path);
+        result.unwrap();
+        assert_eq!(reopened.template, document.template);
+        assert!(!reopened.is_dirty());
+        assert_eq!(reopened.name, "DRUMGEN_");
+    }
+
+    #[test]
+    fn locking_a_step_is_not_an_unsaved_change() {
+        let mut document = Document::new("DGT_0001");
+        document.template.parts[2].sequence[1].set_locked(StepMask(1 << 5));
+        assert!(!document.is_dirty());
+        document.template.parts[2].sequence[1].set_step(5, 4);
+        assert!(document.is_dirty());
+    }
+
+    #[test]
+    fn filename_follows_ngen_rules() {
+        assert_eq!(Document::new("dgt_0002").get_filename(), "DGT_0002.HEX");
+        assert_eq!(Document::new("my kick").get_filename(), "MY_KICK.HEX");
+    }
+
+    #[test]
+    fn undoing_back_to_the_saved_template_is_clean() {
+        let mut document = Document::new("DGT_0001");
+        document.history.record(&document.template, false);
+        document.template.parts[1].sequence[2].set_step(4, 3);
+        document.history.record(&document.template, false);
+        assert!(document.is_dirty());
+        document.undo();
+        assert!(!document.is_dirty());
+        document.redo();
+        assert!(document.is_dirty());
+    }
+
+    #[test]
+    fn opening_a_file_is_not_an_undo_step() {
+        let path = std::env::temp_dir().join(format!("drumgen_document_open_{}.hex", std::process::id()));
+        let mut opened = DrumGen::new();
+        opened.parts[3].sequence[0].set_step(7, 9);
+        opened.save_file(&path).unwrap();
+
+        // One frame of the editor: an edit is recorded, then a file is opened and recorded too.
+        let mut document = Document::new("DGT_0001");
+        document.history.record(&document.template, false);
+        document.template.parts[0].sequence[0].set_step(0, 10);
+        document.history.record(&document.template, false);
+        let result = document.open_file(&path);
+        let _ = std::fs::remove_file(&path);
+        result.unwrap();
+        document.history.record(&document.template, false);
+
+        assert!(!document.history.can_undo());
+        document.undo();
+        assert_eq!(document.template, opened);
+    }
+
+    #[test]
+    fn project_collects_name_and_tags() {
+        let mut document = Document::new("KICKS");
+        document.tags_input = " house, ,techno ".to_string();
+        let project = document.project();
+        assert_eq!(project.metadata.name, "KICKS");
+        assert_eq!(project.metadata.tags, vec!["house".to_string(), "techno".to_string()]);
+    }
+}
@@ -1,101 +1,284 @@
-#![allow(dead_code)]
 use rand::prelude::*;
+use std::fmt;
 use std::fs;
+use std::io;
 use std::io::prelude::*;
+use std::ops::Range;
+use std::path::Path;
 use log::{info, debug};
 
-#[derive(Debug, Copy, Clone)]
-/*
-    DrumGenSequence is a struct that represents a sequence of 32 steps.
-    Each step can have a value between 0 - 10 that determines the probability of the step (also from 0-10).
-    The sequence is stored in an array of 32 u8 values.
-*/
+/// Size in bytes of an encoded sequence (32 steps, two nibbles per byte).
+pub const SEQUENCE_SIZE: usize = 16;
+/// Number of steps in a sequence.
+pub const STEP_COUNT: usize = SEQUENCE_SIZE * 2;
+/// Size in bytes of an encoded part (3 velocity layers).
+pub const LAYER_SIZE: usize = SEQUENCE_SIZE * 3;
+/// Size in bytes of a complete DrumGen template (4 parts).
+pub const TEMPLATE_SIZE: usize = LAYER_SIZE * 4;
+/// Highest probability value a step can hold.
+pub const MAX_STEP_VALUE: u8 = 10;
+/// MIDI velocity played by each of the three layers of a part.
+pub const LAYER_VELOCITIES: [u8; 3] = [127, 100, 60];
+
+/// Error returned by every fallible template operation
+/// (reading, writing and decoding .hex data).
+#[derive(Debug)]
+pub enum DrumGenError {
+    /// Reading or writing the file failed.
+    Io(io::Error),
+    /// The data does not have the expected number of bytes.
+    InvalidSize { expected: usize, actual: usize },
+    /// A nibble holds a value outside of the 0-10 probability range.
+    InvalidNibble { offset: usize, value: u8 },
+    /// A line of a text template could not be parsed.
+    InvalidText { line: usize, message: String },
+    /// A step holds a value outside of the 0-10 probability range.
+    InvalidStep { part: usize, layer: usize, step: usize, value: u8 },
+    /// A JSON, TOML or YAML project could not be read or written.
+    Format(String),
+    /// A template name or file name that NGEN can't use.
+    InvalidName(String),
+    /// A MIDI file could not be read.
+    InvalidMidi(String),
+}
+
+impl DrumGenError {
+    // Shift the byte offset of a nibble error so it is relative to the enclosing data.
+    fn with_offset(self, base: usize) -> DrumGenError {
+        match self {
+            DrumGenError::InvalidNibble { offset, value } => DrumGenError::InvalidNibble { offset: offset + base, value },
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for DrumGenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DrumGenError::Io(err) => write!(f, "I/O error: {}", err),
+            DrumGenError::InvalidSize { expected, actual } => {
+                write!(f, "invalid template size: expected {} bytes, got {}", expected, actual)
+            }
+            DrumGenError::InvalidNibble { offset, value } => {
+                write!(f, "invalid step value {} at byte 0x{:02X} (expected 0-{})", value, offset, MAX_STEP_VALUE)
+            }
+            DrumGenError::InvalidText { line, message } => write!(f, "line {}: {}", line, message),
+            DrumGenError::InvalidStep { part, layer, step, value } => write!(
+                f,
+                "invalid value {} for part {}, layer {}, step {} (expected 0-{})",
+                value, part + 1, layer + 1, step + 1, MAX_STEP_VALUE
+            ),
+            DrumGenError::Format(message) => write!(f, "invalid project: {}", message),
+            DrumGenError::InvalidName(message) => write!(f, "invalid template name: {}", message),
+            DrumGenError::InvalidMidi(message) => write!(f, "invalid MIDI file: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for DrumGenError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DrumGenError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DrumGenError {
+    fn from(err: io::Error) -> DrumGenError {
+        DrumGenError::Io(err)
+    }
+}
+
+/// A set of steps of a sequence, one bit per step (bit `n` is step `n`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct StepMask(pub u32);
+
+impl StepMask {
+    /// No steps.
+    pub const NONE: StepMask = StepMask(0);
+
+    pub fn contains(&self, step: usize) -> bool {
+        step < STEP_COUNT && self.0 & (1 << step) != 0
+    }
+
+    /// Add or remove a step.
+    pub fn set(&mut self, step: usize, included: bool) {
+        if step < STEP_COUNT {
+            if included {
+                self.0 |= 1 << step;
+            } else {
+                self.0 &= !(1 << step);
+            }
+        }
+    }
+
+    pub fn toggle(&mut self, step: usize) {
+        self.set(step, !self.contains(step));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// A sequence of 32 steps.
+///
+/// Each step holds a value between 0 - 10 that determines the probability
+/// of the step being played (0% - 100%). The `_range` methods ignore the part
+/// of a range past the last step.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DrumGenSequence {
     pub steps: [u8; 32],
+    // Steps the bulk edits leave alone, see `locked`.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "StepMask::is_empty"))]
+    locked: StepMask,
 }
 
 impl DrumGenSequence {
+    /// Create an empty sequence with every step set to 0.
     pub fn new() -> DrumGenSequence {
         DrumGenSequence {
             steps: [0; 32],
+            locked: StepMask::NONE,
         }
     }
-    
+
+    /// Steps that the bulk edits (randomize, shift, add, set, clear, scale, repeat,
+    /// Euclidean rhythms, mutate and pasting a StepClip) leave alone. [`set_step`](Self::set_step) still changes them.
+    /// Not stored in .hex files, only in projects.
+    pub fn locked(&self) -> StepMask {
+        self.locked
+    }
+
+    /// Replace the locked steps.
+    pub fn set_locked(&mut self, locked: StepMask) {
+        self.locked = locked;
+    }
+
+    /// Set a step, clamping the value to 0 - 10.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `step` is not in `0..32`.
     pub fn set_step(&mut self, step: usize, value: u8) {
         match step {
             0..=31 => {
-                let final_value = match value {
-                    0..=15 => value,
-                    16..=255 => 15,
-                };
-                self.steps[step] = final_value
+                self.steps[step] = value.min(MAX_STEP_VALUE)
             },
             _ => panic!("Step out of range"),
         }
     }
 
-    /*
-        Encode the sequence to a byte array.
-    */
+    /// Encode the sequence to a 16 byte array.
+    ///
+    /// Byte `n` holds step `2n` in its low nibble and step `2n + 1` in its high nibble.
+    /// Values above 10 are clamped so the output always decodes.
     pub fn convert(&self) -> Vec<u8> {
-        let mut result: Vec<u8> = vec![0; 16];
+        let mut result: Vec<u8> = vec![0; SEQUENCE_SIZE];
         for i in 0..self.steps.len() {
-            let step_value = self.steps[i];
+            let step_value = self.steps[i].min(MAX_STEP_VALUE);
             let index = i / 2;
             if i % 2 == 0 {
-                result[index] = (step_value % 16) & 15;
+                result[index] = step_value;
             } else {
-                let mut value = (step_value % 16) & 15;
-                value = value << 4;
-                result[index] = result[index] | value;
+                result[index] |= step_value << 4;
             }
 
         }
         result
     }
 
-    pub fn decode(&mut self, data: Vec<u8>) {
+    /// Decode a 16 byte array into the sequence (the inverse of [`convert`](Self::convert)).
+    ///
+    /// The sequence is left untouched if the data is invalid.
+    pub fn decode(&mut self, data: Vec<u8>) -> Result<(), DrumGenError> {
         debug!("Decoding data: {:?} ({} bytes)", data, data.len());
-        for i in 0..(self.steps.len() - 1) {
+        if data.len() != SEQUENCE_SIZE {
+            return Err(DrumGenError::InvalidSize { expected: SEQUENCE_SIZE, actual: data.len() });
+        }
+        let mut steps = self.steps;
+        for i in 0..self.steps.len() {
             let index = i / 2;
             let value = data[index];
             debug!("i: {}, index: {}, value: {}", i, index, value);
-            if i % 2 == 0 {
-                self.steps[i] = value & 15;
+            let step_value = if i % 2 == 0 {
+                value & 15
             } else {
-                self.steps[i] = value >> 4;
+                value >> 4
+            };
+            if step_value > MAX_STEP_VALUE {
+                return Err(DrumGenError::InvalidNibble { offset: index, value: step_value });
             }
+            steps[i] = step_value;
         }
+        self.steps = steps;
+        Ok(())
     }
 
-    pub fn randomize(&mut self, min: u8, max: u8, probability: u8) {
-        // Create random object
-        let mut rng = rand::thread_rng();
-        for i in 0..self.steps.len() {
+    /// Replace steps with random values between `min` and `max`.
+    ///
+    /// Each step is replaced with a `probability`% chance (0 - 100). The values
+    /// come from `rng`, so a seeded generator always gives the same result.
+    pub fn randomize<R: Rng + ?Sized>(&mut self, min: u8, max: u8, probability: u8, rng: &mut R) {
+        self.randomize_range(0..STEP_COUNT, min, max, probability, rng);
+    }
+
+    /// [`randomize`](Self::randomize) limited to a range of steps.
+    pub fn randomize_range<R: Rng + ?Sized>(&mut self, steps: Range<usize>, min: u8, max: u8, probability: u8, rng: &mut R) {
+        for i in clamp_steps(steps) {
             // Generate random value between 0 - 10
             if rng.gen_range(0..100) < probability {
                 if min > max {
-                    let value : u8 = rng.gen_range(max..min) as u8;
-                    self.set_step(i, value);
+                    let value : u8 = rng.gen_range(max..min);
+                    self.set_unlocked(i, value);
 
-                } else 
-                if max - min == 0 {
-                    self.set_step(i, min);
+                } else if max - min == 0 {
+                    self.set_unlocked(i, min);
                 } else {
-                    let value : u8 = rng.gen_range(min..max) as u8;
-                    self.set_step(i, value);
+                    let value : u8 = rng.gen_range(min..max);
+                    self.set_unlocked(i, value);
                 }
             }
         }
     }
 
-    pub fn repeat(&mut self, start: usize, steps: usize) {
-        for i in start..self.steps.len() {
-            let step = self.steps[start + ((i - start) % steps)];
-            self.set_step(i, step);
+    /// Copy the `period` steps starting at `from` into the `count` periods right after
+    /// them, stopping at the end of the sequence. Pass [`STEP_COUNT`] as `count` to
+    /// repeat them to the end.
+    ///
+    /// ```
+    /// use drumgen::DrumGenSequence;
+    ///
+    /// let mut sequence = DrumGenSequence::new();
+    /// sequence.set_step(0, 10);
+    /// sequence.set_step(1, 5);
+    /// sequence.repeat(0, 4, 2);
+    /// assert_eq!(&sequence.steps[..14], &[10, 5, 0, 0, 10, 5, 0, 0, 10, 5, 0, 0, 0, 0]);
+    /// ```
+    pub fn repeat(&mut self, from: usize, period: usize, count: usize) {
+        if period == 0 || from >= STEP_COUNT {
+            return;
+        }
+        let source = self.steps;
+        let start = from.saturating_add(period).min(STEP_COUNT);
+        let end = period.saturating_mul(count).saturating_add(start).min(STEP_COUNT);
+        for step in start..end {
+            self.set_unlocked(step, source[from + (step - from) % period]);
         }
     }
 
+    // Set a step unless it is locked.
+    pub(crate) fn set_unlocked(&mut self, step: usize, value: u8) {
+        if !self.locked.contains(step) {
+            self.set_step(step, value);
+        }
+    }
+
+    /// Get the value of a step, or 0 if `step` is out of range.
     pub fn get_step(&self, step: usize) -> u8 {
         if step < self.steps.len() {
             self.steps[step]
@@ -104,136 +287,927 @@ impl DrumGenSequence {
         }
     }
 
+    /// Rotate the sequence. Positive values move steps to the left, negative values to the right.
     pub fn shift(&mut self, value: i8) {
-        let mut new_steps: [u8; 32] = [0; 32];
-        for i in 0..self.steps.len() {
-            let index: i16 = (i as i16 + value as i16) % self.steps.len() as i16;
-            if index < 0 {
-                new_steps[i] = self.steps[(self.steps.len() as i16 + index) as usize];
-            } else {
-                new_steps[i] = self.steps[index as usize];
-            }
+        self.shift_range(0..STEP_COUNT, value);
+    }
+
+    /// Rotate a range of steps, leaving the others in place.
+    ///
+    /// Locked steps stay where they are; the other steps rotate around them.
+    pub fn shift_range(&mut self, steps: Range<usize>, value: i8) {
+        let unlocked: Vec<usize> = clamp_steps(steps).filter(|step| !self.locked.contains(*step)).collect();
+        if unlocked.is_empty() {
+            return;
+        }
+        let old_steps = self.steps;
+        let length = unlocked.len() as i16;
+        for (i, step) in unlocked.iter().enumerate() {
+            let index = (i as i16 + value as i16).rem_euclid(length) as usize;
+            self.steps[*step] = old_steps[unlocked[index]];
         }
-        self.steps = new_steps;
     }
 
+    /// Add `value` to every step, clamping the result to 0 - 10.
     pub fn add(&mut self, value:i8) {
-        for i in 0..self.steps.len() {
-            let mut new_value = self.steps[i] as i8 + value;
-            new_value = {
-                if new_value < 0 {
-                    0
-                } else if new_value > 10 {
-                    10
-                } else {
-                    new_value
-                }
-            };
-            self.steps[i] = new_value as u8;
+        self.add_range(0..STEP_COUNT, value);
+    }
+
+    /// [`add`](Self::add) limited to a range of steps.
+    pub fn add_range(&mut self, steps: Range<usize>, value: i8) {
+        for step in clamp_steps(steps) {
+            self.set_unlocked(step, (self.steps[step] as i8 + value).clamp(0, MAX_STEP_VALUE as i8) as u8);
         }
     }
 
+    /// Set every step to 0.
     pub fn clear(&mut self) {
-        for i in 0..self.steps.len() {
-            self.steps[i] = 0;
+        self.clear_range(0..STEP_COUNT);
+    }
+
+    /// Set a range of steps to 0.
+    pub fn clear_range(&mut self, steps: Range<usize>) {
+        self.set_range(steps, 0);
+    }
+
+    /// Set a range of steps to the same value, clamped to 0 - 10.
+    pub fn set_range(&mut self, steps: Range<usize>, value: u8) {
+        for step in clamp_steps(steps) {
+            self.set_unlocked(step, value);
+        }
+    }
+
+    /// Make a variation of the sequence.
+    ///
+    /// Each step that can play is changed with an `amount` (0.0 - 1.0) chance:
+    /// usually its probability is nudged up or down by 1 or 2, sometimes the hit
+    /// moves to an empty neighbouring step instead. Locked steps are never changed,
+    /// and hits don't move onto them. The same seed always gives the same variation.
+    pub fn mutate(&mut self, amount: f32, seed: u64) {
+        self.mutate_with(amount, &mut rand::rngs::StdRng::seed_from_u64(seed));
+    }
+
+    pub(crate) fn mutate_with<R: Rng + ?Sized>(&mut self, amount: f32, rng: &mut R) {
+        // Chance of a changed hit moving rather than being nudged.
+        const MOVE_CHANCE: f64 = 0.25;
+        let amount = amount.clamp(0.0, 1.0) as f64;
+        let original = self.steps;
+        for step in 0..STEP_COUNT {
+            if original[step] == 0 || self.locked.contains(step) || !rng.gen_bool(amount) {
+                continue;
+            }
+            if rng.gen_bool(MOVE_CHANCE) {
+                let neighbour = (if rng.gen_bool(0.5) { step + 1 } else { step + STEP_COUNT - 1 }) % STEP_COUNT;
+                if original[neighbour] == 0 && self.steps[neighbour] == 0 && !self.locked.contains(neighbour) {
+                    self.steps[neighbour] = self.steps[step];
+                    self.steps[step] = 0;
+                    continue;
+                }
+            }
+            let nudge = rng.gen_range(1..=2) * if rng.gen_bool(0.5) { 1 } else { -1 };
+            self.steps[step] = (self.steps[step] as i8 + nudge).clamp(0, MAX_STEP_VALUE as i8) as u8;
         }
     }
+
+    /// Multiply a range of steps by `factor`, rounding and clamping the result to 0 - 10.
+    pub fn scale_range(&mut self, steps: Range<usize>, factor: f32) {
+        for step in clamp_steps(steps) {
+            self.set_unlocked(step, (self.steps[step] as f32 * factor).round().clamp(0.0, MAX_STEP_VALUE as f32) as u8);
+        }
+    }
+}
+
+// The part of a range of steps that lies within a sequence (empty if the range is backwards).
+pub(crate) fn clamp_steps(steps: Range<usize>) -> Range<usize> {
+    let end = steps.end.min(STEP_COUNT);
+    steps.start.min(end)..end
 }
 
-#[derive(Debug, Copy, Clone)]
+/// A DrumGen part: three sequences, one per velocity layer (127, 100 and 60).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DrumGenLayer {
     pub sequence: [DrumGenSequence; 3]
 }
 
 impl DrumGenLayer {
+    /// Create a part with three empty sequences.
     pub fn new() -> DrumGenLayer {
         DrumGenLayer {
             sequence: [DrumGenSequence::new(); 3]
         }
     }
 
+    /// Make a variation of every layer, see [`DrumGenSequence::mutate`].
+    pub fn mutate(&mut self, amount: f32, seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        for sequence in self.sequence.iter_mut() {
+            sequence.mutate_with(amount, &mut rng);
+        }
+    }
+
+    /// Encode the part to a 48 byte array (the three sequences back to back).
     pub fn convert(&self) -> Vec<u8> {
-        let mut result: Vec<u8> = vec![0; 48];
+        let mut result: Vec<u8> = vec![0; LAYER_SIZE];
         for i in 0..self.sequence.len() {
             let sequence = &self.sequence[i];
             let sequence_result = sequence.convert();
-            for j in 0..sequence_result.len() {
-                let index = j + (i * 16);
-                result[index] = sequence_result[j];
+            for (j, byte) in sequence_result.iter().enumerate() {
+                let index = j + (i * SEQUENCE_SIZE);
+                result[index] = *byte;
             }
         }
         result
     }
 
-    pub fn decode(&mut self, data: Vec<u8>) {
-        for i in 0..self.sequence.len() {
+    /// Decode a 48 byte array into the three velocity layers.
+    ///
+    /// The part is left untouched if the data is invalid.
+    pub fn decode(&mut self, data: Vec<u8>) -> Result<(), DrumGenError> {
+        if data.len() != LAYER_SIZE {
+            return Err(DrumGenError::InvalidSize { expected: LAYER_SIZE, actual: data.len() });
+        }
+        let mut sequence = self.sequence;
+        for (i, seq) in sequence.iter_mut().enumerate() {
             let mut sequence_data: Vec<u8> = Vec::new();
-            for j in 0..16 {
-                let index = j + (i * 16);
+            for j in 0..SEQUENCE_SIZE {
+                let index = j + (i * SEQUENCE_SIZE);
                 sequence_data.push(data[index]);
             }
-            self.sequence[i].decode(sequence_data);
+            seq.decode(sequence_data).map_err(|err| err.with_offset(i * SEQUENCE_SIZE))?;
+        }
+        self.sequence = sequence;
+        Ok(())
+    }
+
+    /// Roll the dice for one step of the part and return the velocity layer that plays, if any.
+    ///
+    /// Layers are tried from the loudest (127) to the softest (60) and the first
+    /// one whose probability hits plays, so a part never plays twice on a step.
+    pub fn roll_step<R: Rng + ?Sized>(&self, step: usize, rng: &mut R) -> Option<usize> {
+        for (layer, sequence) in self.sequence.iter().enumerate() {
+            let value = sequence.get_step(step).min(MAX_STEP_VALUE);
+            if rng.gen_range(0..MAX_STEP_VALUE) < value {
+                return Some(layer);
+            }
+        }
+        None
+    }
+
+    /// Chance of each layer playing on a step, following the rule of
+    /// [`roll_step`](Self::roll_step): a layer only gets its chance when all
+    /// louder layers missed, so its probability is scaled by their misses.
+    pub fn outcome(&self, step: usize) -> StepOutcome {
+        let mut outcome = StepOutcome::default();
+        let mut remaining = 1.0;
+        for (chance, sequence) in outcome.layers.iter_mut().zip(self.sequence.iter()) {
+            let value = sequence.get_step(step).min(MAX_STEP_VALUE) as f32 / MAX_STEP_VALUE as f32;
+            *chance = remaining * value;
+            remaining -= *chance;
         }
+        outcome
+    }
+
+    /// [`outcome`](Self::outcome) of every step.
+    pub fn outcomes(&self) -> [StepOutcome; STEP_COUNT] {
+        std::array::from_fn(|step| self.outcome(step))
     }
+
+    /// Steps where more than one layer is set. On these the quieter layers play
+    /// less often than their value says, or not at all.
+    pub fn overlaps(&self) -> Vec<LayerOverlap> {
+        (0..STEP_COUNT)
+            .filter_map(|step| {
+                let layers: Vec<usize> = (0..3).filter(|layer| self.sequence[*layer].get_step(step) > 0).collect();
+                if layers.len() < 2 {
+                    return None;
+                }
+                let outcome = self.outcome(step);
+                let shadowed = layers.iter().copied().filter(|layer| outcome.layers[*layer] == 0.0).collect();
+                Some(LayerOverlap { step, layers, shadowed })
+            })
+            .collect()
+    }
+
+    /// Leave one layer on each overlapping step: the one most likely to play
+    /// (the louder one on a tie), set to the step's overall chance of playing.
+    /// Steps with a locked layer are left alone.
+    pub fn normalize_layers(&mut self) {
+        for overlap in self.overlaps() {
+            let step = overlap.step;
+            if self.sequence.iter().any(|sequence| sequence.locked.contains(step)) {
+                continue;
+            }
+            let outcome = self.outcome(step);
+            let mut keep = 0;
+            for layer in 1..3 {
+                if outcome.layers[layer] > outcome.layers[keep] {
+                    keep = layer;
+                }
+            }
+            let value = (outcome.hit() * MAX_STEP_VALUE as f32).round().max(1.0) as u8;
+            for (layer, sequence) in self.sequence.iter_mut().enumerate() {
+                sequence.set_step(step, if layer == keep { value } else { 0 });
+            }
+        }
+    }
+
+    /// Chance (0.0-1.0) that any layer of the part plays on a step.
+    pub fn hit_probability(&self, step: usize) -> f32 {
+        let miss: f32 = self
+            .sequence
+            .iter()
+            .map(|sequence| 1.0 - sequence.get_step(step).min(MAX_STEP_VALUE) as f32 / MAX_STEP_VALUE as f32)
+            .product();
+        1.0 - miss
+    }
+}
+
+/// Chances of what a part plays on one step.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct StepOutcome {
+    /// Chance (0.0-1.0) of each velocity layer playing, loudest first.
+    pub layers: [f32; 3],
 }
 
-#[derive(Debug, Copy, Clone)]
+impl StepOutcome {
+    /// Chance that any layer plays.
+    pub fn hit(&self) -> f32 {
+        self.layers.iter().sum()
+    }
+
+    /// Chance that the part stays silent.
+    pub fn silence(&self) -> f32 {
+        1.0 - self.hit()
+    }
+}
+
+/// A step where more than one layer of a part is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerOverlap {
+    pub step: usize,
+    /// Layers with a probability above 0 on the step, loudest first.
+    pub layers: Vec<usize>,
+    /// Layers that are set but never play, because a louder layer always does.
+    pub shadowed: Vec<usize>,
+}
+
+/// A complete DrumGen template: four parts with three velocity layers each.
+///
+/// Templates are stored on the NGEN SD card as 192 byte .hex files.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DrumGen {
     pub parts: [DrumGenLayer; 4]
 }
 
 impl DrumGen {
+    /// Create an empty template.
     pub fn new() -> DrumGen {
         DrumGen {
             parts: [DrumGenLayer::new(); 4]
         }
     }
 
+    /// Encode the template to its 192 byte .hex representation.
     pub fn convert(&self) -> Vec<u8> {
         let mut result: Vec<u8> = Vec::new();
         for i in 0..self.parts.len() {
             let layer = &self.parts[i];
             let layer_result = layer.convert();
-            for j in 0..layer_result.len() {
-                result.push(layer_result[j]);
-            }
+            result.extend_from_slice(&layer_result);
         }
         result
     }
 
-    pub fn parse_file(&mut self, filepath: String) {
-        let mut file = fs::File::open(&filepath).unwrap();
+    /// Read a .hex file into the template.
+    ///
+    /// The template is left untouched if the file can't be read or is invalid.
+    pub fn parse_file<P: AsRef<Path>>(&mut self, filepath: P) -> Result<(), DrumGenError> {
+        let filepath = filepath.as_ref();
+        let mut file = fs::File::open(filepath)?;
 
         // Read file content and parse to bytes
         let mut data: Vec<u8> = Vec::new();
-        file.read_to_end(&mut data).unwrap();
-        info!("Parsing file: {}", filepath);
+        file.read_to_end(&mut data)?;
+        info!("Parsing file: {}", filepath.display());
         info!("File size: {}", data.len());
         debug!("File content: {:?}", data);
-        self.decode(data);
+        self.decode(data)
     }
 
-    pub fn save_file(&self, filepath: String) {
-        let mut file = fs::File::create(&filepath).unwrap();
+    /// Write the template to a .hex file, replacing it if it exists.
+    pub fn save_file<P: AsRef<Path>>(&self, filepath: P) -> Result<(), DrumGenError> {
+        let filepath = filepath.as_ref();
+        let mut file = fs::File::create(filepath)?;
         let data = self.convert();
         // Save data to file
-        info!("Saving file to: {}", filepath);
-        let _ = file.write_all(&data);
+        info!("Saving file to: {}", filepath.display());
+        file.write_all(&data)?;
         info!("File size: {}", data.len());
         debug!("File content: {:?}", data);
+        Ok(())
     }
 
-    pub fn decode(&mut self, data: Vec<u8>) {
-        for i in 0..self.parts.len() {
+    /// Check that every step holds a value between 0 and 10.
+    ///
+    /// Templates built by hand or deserialized from a project can hold values
+    /// that the .hex format cannot store.
+    pub fn validate(&self) -> Result<(), DrumGenError> {
+        for (part, layer_data) in self.parts.iter().enumerate() {
+            for (layer, sequence) in layer_data.sequence.iter().enumerate() {
+                for (step, value) in sequence.steps.iter().enumerate() {
+                    if *value > MAX_STEP_VALUE {
+                        return Err(DrumGenError::InvalidStep { part, layer, step, value: *value });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Make a variation of every part, see [`DrumGenSequence::mutate`].
+    pub fn mutate(&mut self, amount: f32, seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        for sequence in self.parts.iter_mut().flat_map(|part| part.sequence.iter_mut()) {
+            sequence.mutate_with(amount, &mut rng);
+        }
+    }
+
+    /// Blend two templates step by step: `amount` 0.0 gives `a`, 1.0 gives `b`
+    /// and values in between interpolate each probability, rounded to the nearest value.
+    pub fn morph(a: &DrumGen, b: &DrumGen, amount: f32) -> DrumGen {
+        let amount = amount.clamp(0.0, 1.0);
+        a.zip_steps(b, |x, y| (x as f32 + (y as f32 - x as f32) * amount).round() as u8)
+    }
+
+    /// Mix two templates by taking every step either from `a` or from `b`, with
+    /// `b` chosen for about `amount` (0.0 - 1.0) of the steps.
+    ///
+    /// The steps are picked by a mask seeded with `seed`. For the same seed, raising
+    /// `amount` only ever switches more steps over to `b`.
+    pub fn crossfade(a: &DrumGen, b: &DrumGen, amount: f32, seed: u64) -> DrumGen {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        a.zip_steps(b, |x, y| if rng.gen::<f32>() < amount { y } else { x })
+    }
+
+    // New template with every step computed from the same step of both templates.
+    fn zip_steps(&self, other: &DrumGen, mut f: impl FnMut(u8, u8) -> u8) -> DrumGen {
+        let mut result = *self;
+        for (part, other_part) in result.parts.iter_mut().zip(other.parts.iter()) {
+            for (sequence, other_sequence) in part.sequence.iter_mut().zip(other_part.sequence.iter()) {
+                for (step, other_step) in sequence.steps.iter_mut().zip(other_sequence.steps) {
+                    *step = f(*step, other_step).min(MAX_STEP_VALUE);
+                }
+            }
+        }
+        result
+    }
+
+    /// Decode a complete 192 byte template.
+    ///
+    /// The template is left untouched if the data is invalid.
+    pub fn decode(&mut self, data: Vec<u8>) -> Result<(), DrumGenError> {
+        if data.len() != TEMPLATE_SIZE {
+            return Err(DrumGenError::InvalidSize { expected: TEMPLATE_SIZE, actual: data.len() });
+        }
+        let mut parts = self.parts;
+        for (i, part) in parts.iter_mut().enumerate() {
             let mut layer_data: Vec<u8> = Vec::new();
-            for j in 0..48 {
-                let index = j + (i * 48);
+            for j in 0..LAYER_SIZE {
+                let index = j + (i * LAYER_SIZE);
                 layer_data.push(data[index]);
             }
-            self.parts[i].decode(layer_data);
+            part.decode(layer_data).map_err(|err| err.with_offset(i * LAYER_SIZE))?;
         }
+        self.parts = parts;
+        Ok(())
     }
+}
 
 
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+
+    const EMPTY_HEX: &[u8] = include_bytes!("../tests/fixtures/empty.hex");
+    const FOUR_ON_FLOOR_HEX: &[u8] = include_bytes!("../tests/fixtures/four_on_floor.hex");
+    const RAMP_HEX: &[u8] = include_bytes!("../tests/fixtures/ramp.hex");
+
+    fn fixture_path(name: &str) -> String {
+        format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn random_template(rng: &mut StdRng) -> DrumGen {
+        let mut template = DrumGen::new();
+        for part in template.parts.iter_mut() {
+            for sequence in part.sequence.iter_mut() {
+                for step in sequence.steps.iter_mut() {
+                    *step = rng.gen_range(0..=MAX_STEP_VALUE);
+                }
+            }
+        }
+        template
+    }
+
+    fn four_on_floor() -> DrumGen {
+        let mut template = DrumGen::new();
+        for i in (0..32).step_by(4) {
+            template.parts[0].sequence[0].set_step(i, 10);
+        }
+        for i in [4, 12, 20, 28] {
+            template.parts[1].sequence[0].set_step(i, 10);
+        }
+        template.parts[1].sequence[2].set_step(30, 3);
+        for i in 0..32 {
+            template.parts[2].sequence[1].set_step(i, if i % 2 == 0 { 5 } else { 2 });
+        }
+        template.parts[3].sequence[2].set_step(31, 7);
+        template
+    }
+
+    #[test]
+    fn sequence_round_trips_every_value_at_every_step() {
+        for step in 0..32 {
+            for value in 0..=MAX_STEP_VALUE {
+                let mut sequence = DrumGenSequence::new();
+                sequence.set_step(step, value);
+                let data = sequence.convert();
+                assert_eq!(data.len(), SEQUENCE_SIZE);
+
+                let expected_byte = if step % 2 == 0 { value } else { value << 4 };
+                assert_eq!(data[step / 2], expected_byte, "step {} value {}", step, value);
+
+                let mut decoded = DrumGenSequence::new();
+                decoded.decode(data).unwrap();
+                assert_eq!(decoded.steps, sequence.steps, "step {} value {}", step, value);
+            }
+        }
+    }
 
+    #[test]
+    fn sequence_round_trips_every_valid_byte() {
+        for index in 0..SEQUENCE_SIZE {
+            for low in 0..=MAX_STEP_VALUE {
+                for high in 0..=MAX_STEP_VALUE {
+                    let mut data = vec![0; SEQUENCE_SIZE];
+                    data[index] = low | (high << 4);
+                    let mut sequence = DrumGenSequence::new();
+                    sequence.decode(data.clone()).unwrap();
+                    assert_eq!(sequence.steps[index * 2], low);
+                    assert_eq!(sequence.steps[index * 2 + 1], high);
+                    assert_eq!(sequence.convert(), data);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sequence_keeps_last_step() {
+        let mut sequence = DrumGenSequence::new();
+        sequence.set_step(31, 10);
+        let mut decoded = DrumGenSequence::new();
+        decoded.decode(sequence.convert()).unwrap();
+        assert_eq!(decoded.get_step(31), 10);
+    }
+
+    #[test]
+    fn sequence_convert_clamps_out_of_range_steps() {
+        let mut sequence = DrumGenSequence::new();
+        sequence.steps[0] = 15;
+        sequence.steps[1] = 200;
+        assert_eq!(sequence.convert()[0], 0xAA);
+    }
+
+    #[test]
+    fn sequence_decode_rejects_invalid_data() {
+        let mut sequence = DrumGenSequence::new();
+        assert!(matches!(
+            sequence.decode(vec![0; 15]),
+            Err(DrumGenError::InvalidSize { expected: 16, actual: 15 })
+        ));
+
+        let mut data = vec![0x11; SEQUENCE_SIZE];
+        data[3] = 0xB1;
+        assert!(matches!(
+            sequence.decode(data),
+            Err(DrumGenError::InvalidNibble { offset: 3, value: 11 })
+        ));
+        assert_eq!(sequence.steps, [0; 32]);
+    }
+
+    #[test]
+    fn layer_round_trips_random_sequences() {
+        let mut rng = StdRng::seed_from_u64(0x5EED);
+        for _ in 0..200 {
+            let layer = random_template(&mut rng).parts[0];
+            let data = layer.convert();
+            assert_eq!(data.len(), LAYER_SIZE);
+            let mut decoded = DrumGenLayer::new();
+            decoded.decode(data).unwrap();
+            for i in 0..3 {
+                assert_eq!(decoded.sequence[i].steps, layer.sequence[i].steps);
+            }
+        }
+    }
+
+    #[test]
+    fn layer_decode_reports_offset_of_invalid_nibble() {
+        let mut data = vec![0; LAYER_SIZE];
+        data[SEQUENCE_SIZE * 2 + 5] = 0xF0;
+        let mut layer = DrumGenLayer::new();
+        assert!(matches!(
+            layer.decode(data),
+            Err(DrumGenError::InvalidNibble { offset: 37, value: 15 })
+        ));
+    }
+
+    #[test]
+    fn template_round_trips_random_templates() {
+        let mut rng = StdRng::seed_from_u64(0xD5A1);
+        for _ in 0..200 {
+            let template = random_template(&mut rng);
+            let data = template.convert();
+            assert_eq!(data.len(), TEMPLATE_SIZE);
+            let mut decoded = DrumGen::new();
+            decoded.decode(data.clone()).unwrap();
+            assert_eq!(decoded.convert(), data);
+            for p in 0..4 {
+                for l in 0..3 {
+                    assert_eq!(decoded.parts[p].sequence[l].steps, template.parts[p].sequence[l].steps);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn template_decode_rejects_invalid_data() {
+        let mut template = four_on_floor();
+        assert!(matches!(
+            template.decode(vec![0; 191]),
+            Err(DrumGenError::InvalidSize { expected: 192, actual: 191 })
+        ));
+        assert!(matches!(
+            template.decode(vec![0; 193]),
+            Err(DrumGenError::InvalidSize { expected: 192, actual: 193 })
+        ));
+
+        let mut data = vec![0; TEMPLATE_SIZE];
+        data[TEMPLATE_SIZE - 1] = 0xC0;
+        assert!(matches!(
+            template.decode(data),
+            Err(DrumGenError::InvalidNibble { offset: 191, value: 12 })
+        ));
+        assert_eq!(template.convert(), four_on_floor().convert());
+    }
+
+    #[test]
+    fn golden_empty_template() {
+        assert_eq!(DrumGen::new().convert(), EMPTY_HEX);
+    }
+
+    #[test]
+    fn golden_four_on_floor_template() {
+        assert_eq!(four_on_floor().convert(), FOUR_ON_FLOOR_HEX);
+
+        let mut decoded = DrumGen::new();
+        decoded.decode(FOUR_ON_FLOOR_HEX.to_vec()).unwrap();
+        assert_eq!(decoded.parts[0].sequence[0].steps[..8], [10, 0, 0, 0, 10, 0, 0, 0]);
+        assert_eq!(decoded.parts[1].sequence[2].get_step(30), 3);
+        assert_eq!(decoded.parts[3].sequence[2].get_step(31), 7);
+        assert_eq!(decoded.convert(), FOUR_ON_FLOOR_HEX);
+    }
+
+    #[test]
+    fn golden_ramp_template() {
+        let mut decoded = DrumGen::new();
+        decoded.decode(RAMP_HEX.to_vec()).unwrap();
+        for p in 0..4 {
+            for l in 0..3 {
+                for i in 0..32 {
+                    let expected = ((i + p * 3 + l) % 11) as u8;
+                    assert_eq!(decoded.parts[p].sequence[l].get_step(i), expected);
+                }
+            }
+        }
+        assert_eq!(decoded.convert(), RAMP_HEX);
+    }
+
+    #[test]
+    fn parse_file_reads_fixture() {
+        let mut template = DrumGen::new();
+        template.parse_file(fixture_path("four_on_floor.hex")).unwrap();
+        assert_eq!(template.convert(), FOUR_ON_FLOOR_HEX);
+    }
+
+    // hand_assembled.hex was written byte by byte from the format description rather
+    // than by `save_file`. It is not an NGEN export, so it only checks that the decoder
+    // follows the documented layout, not that it reads what the device writes:
+    //
+    //   0x00  21 43 65 87 a9 00 ..   part 1, 127: steps 0-9 = 1-10, low nibble first
+    //   0x40  05 a0 00 ..            part 2, 100: step 0 = 5, step 3 = 10
+    //   0x88  07 00 ..               part 3, 60: step 16 = 7
+    //   0xbf  a0                     part 4, 60: step 31 = 10
+    #[test]
+    fn parse_file_decodes_a_hand_assembled_file() {
+        let mut template = DrumGen::new();
+        template.parse_file(fixture_path("hand_assembled.hex")).unwrap();
+        let mut expected = DrumGen::new();
+        expected.parts[0].sequence[0].steps[..10].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+        expected.parts[1].sequence[1].steps[0] = 5;
+        expected.parts[1].sequence[1].steps[3] = 10;
+        expected.parts[2].sequence[2].steps[16] = 7;
+        expected.parts[3].sequence[2].steps[31] = 10;
+        assert_eq!(template, expected);
+    }
+
+    #[test]
+    fn parse_file_reports_missing_file() {
+        let mut template = DrumGen::new();
+        assert!(matches!(
+            template.parse_file(fixture_path("missing.hex")),
+            Err(DrumGenError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn save_file_round_trips() {
+        let path = std::env::temp_dir().join(format!("drumgen_round_trip_{}.hex", std::process::id()));
+        let template = four_on_floor();
+        template.save_file(&path).unwrap();
+        let mut loaded = DrumGen::new();
+        loaded.parse_file(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(loaded.convert(), FOUR_ON_FLOOR_HEX);
+    }
+
+    #[test]
+    fn hit_probability_combines_layers() {
+        let mut part = DrumGenLayer::new();
+        part.sequence[0].set_step(0, 10);
+        part.sequence[0].set_step(1, 5);
+        part.sequence[1].set_step(1, 5);
+        part.sequence[2].set_step(2, 2);
+        assert_eq!(part.hit_probability(0), 1.0);
+        assert!((part.hit_probability(1) - 0.75).abs() < 1e-6);
+        assert!((part.hit_probability(2) - 0.2).abs() < 1e-6);
+        assert_eq!(part.hit_probability(3), 0.0);
+    }
+
+    fn ramp() -> DrumGenSequence {
+        let mut sequence = DrumGenSequence::new();
+        for (i, step) in sequence.steps.iter_mut().enumerate() {
+            *step = (i % 11) as u8;
+        }
+        sequence
+    }
+
+    #[test]
+    fn outcome_follows_the_layer_order() {
+        let mut part = DrumGenLayer::new();
+        part.sequence[0].set_step(0, 5);
+        part.sequence[1].set_step(0, 5);
+        part.sequence[2].set_step(0, 10);
+        let outcome = part.outcome(0);
+        assert_eq!(outcome.layers, [0.5, 0.25, 0.25]);
+        assert_eq!(outcome.silence(), 0.0);
+
+        part.sequence[0].set_step(1, 10);
+        part.sequence[2].set_step(1, 4);
+        part.sequence[1].set_step(2, 3);
+        let outcomes = part.outcomes();
+        assert_eq!(outcomes[1].layers, [1.0, 0.0, 0.0]);
+        assert_eq!(outcomes[2].layers, [0.0, 0.3, 0.0]);
+        assert_eq!(outcomes[3], StepOutcome::default());
+        for (step, outcome) in outcomes.iter().enumerate().take(4) {
+            assert!((outcome.hit() - part.hit_probability(step)).abs() < 1e-6);
+        }
+
+        assert_eq!(part.overlaps(), vec![
+            LayerOverlap { step: 0, layers: vec![0, 1, 2], shadowed: vec![] },
+            LayerOverlap { step: 1, layers: vec![0, 2], shadowed: vec![2] },
+        ]);
+    }
+
+    #[test]
+    fn normalize_keeps_one_layer_per_step() {
+        let mut part = DrumGenLayer::new();
+        part.sequence[0].set_step(0, 2);
+        part.sequence[1].set_step(0, 10);
+        part.sequence[0].set_step(1, 10);
+        part.sequence[2].set_step(1, 4);
+        part.sequence[0].set_step(2, 5);
+        part.sequence[1].set_step(2, 5);
+        part.sequence[0].set_step(3, 3);
+        part.sequence[2].set_step(3, 3);
+        part.sequence[2].set_step(4, 6);
+        part.sequence[2].set_locked(StepMask(1 << 3));
+        part.normalize_layers();
+
+        let column = |step: usize| part.sequence.map(|sequence| sequence.steps[step]);
+        assert_eq!(column(0), [0, 10, 0]);
+        assert_eq!(column(1), [10, 0, 0]);
+        assert_eq!(column(2), [8, 0, 0]);
+        assert_eq!(column(3), [3, 0, 3]);
+        assert_eq!(column(4), [0, 0, 6]);
+        assert_eq!(part.overlaps().len(), 1);
+    }
+
+    #[test]
+    fn shift_rotates_whole_sequence() {
+        let mut sequence = ramp();
+        sequence.shift(1);
+        assert_eq!(sequence.steps[0], 1);
+        assert_eq!(sequence.steps[31], 0);
+        sequence.shift(-3);
+        assert_eq!(&sequence.steps[..4], &[8, 9, 0, 1]);
+    }
+
+    #[test]
+    fn range_operations_leave_other_steps_alone() {
+        let mut sequence = ramp();
+        sequence.shift_range(4..8, 1);
+        assert_eq!(&sequence.steps[..9], &[0, 1, 2, 3, 5, 6, 7, 4, 8]);
+        sequence.shift_range(4..8, -1);
+        assert_eq!(sequence, ramp());
+
+        sequence.set_range(0..2, 12);
+        sequence.add_range(2..4, 9);
+        sequence.scale_range(8..11, 0.5);
+        assert_eq!(&sequence.steps[..12], &[10, 10, 10, 10, 4, 5, 6, 7, 4, 5, 5, 0]);
+
+        sequence.clear_range(0..4);
+        sequence.randomize_range(20..20, 10, 10, 100, &mut StdRng::seed_from_u64(0));
+        assert_eq!(&sequence.steps[..5], &[0, 0, 0, 0, 4]);
+        assert_eq!(&sequence.steps[12..], &ramp().steps[12..]);
+    }
+
+    #[test]
+    fn randomize_is_repeatable_with_a_seed() {
+        let randomized = |seed: u64| {
+            let mut sequence = ramp();
+            sequence.randomize(2, 8, 50, &mut StdRng::seed_from_u64(seed));
+            sequence
+        };
+        let sequence = randomized(7);
+        assert_eq!(sequence, randomized(7));
+        assert_ne!(sequence, randomized(8));
+        let changed: Vec<usize> = (0..STEP_COUNT).filter(|i| sequence.steps[*i] != ramp().steps[*i]).collect();
+        assert!(!changed.is_empty() && changed.len() < STEP_COUNT);
+        assert!(changed.iter().all(|i| (2..8).contains(&sequence.steps[*i])));
+    }
+
+    #[test]
+    fn morph_interpolates_between_templates() {
+        let mut rng = StdRng::seed_from_u64(0x3097);
+        let (a, b) = (random_template(&mut rng), random_template(&mut rng));
+        assert_eq!(DrumGen::morph(&a, &b, 0.0), a);
+        assert_eq!(DrumGen::morph(&a, &b, 1.0), b);
+        assert_eq!(DrumGen::morph(&a, &b, 2.0), b);
+
+        let mut low = DrumGen::new();
+        low.parts[2].sequence[1].set_step(5, 2);
+        let mut high = DrumGen::new();
+        high.parts[2].sequence[1].set_step(5, 10);
+        assert_eq!(DrumGen::morph(&low, &high, 0.5).parts[2].sequence[1].steps[5], 6);
+        assert_eq!(DrumGen::morph(&low, &high, 0.3).parts[2].sequence[1].steps[5], 4);
+    }
+
+    #[test]
+    fn crossfade_picks_whole_steps_by_seed() {
+        let mut rng = StdRng::seed_from_u64(0xC405);
+        let (a, b) = (random_template(&mut rng), random_template(&mut rng));
+        assert_eq!(DrumGen::crossfade(&a, &b, 0.0, 1), a);
+        assert_eq!(DrumGen::crossfade(&a, &b, 1.0, 1), b);
+        assert_eq!(DrumGen::crossfade(&a, &b, 0.5, 1), DrumGen::crossfade(&a, &b, 0.5, 1));
+        assert_ne!(DrumGen::crossfade(&a, &b, 0.5, 1), DrumGen::crossfade(&a, &b, 0.5, 2));
+
+        // Raising the amount only moves more steps over to B.
+        let from_b = |amount: f32| {
+            let mixed = DrumGen::crossfade(&a, &DrumGen::new(), amount, 9).convert();
+            let original = a.convert();
+            (0..TEMPLATE_SIZE).filter(|i| mixed[*i] != original[*i]).collect::<Vec<_>>()
+        };
+        let (quarter, half) = (from_b(0.25), from_b(0.5));
+        assert!(quarter.iter().all(|i| half.contains(i)));
+        assert!(half.len() > quarter.len());
+    }
+
+    #[test]
+    fn mutate_varies_hits_but_not_locked_steps() {
+        let mut rng = StdRng::seed_from_u64(0x4D07);
+        let original = random_template(&mut rng);
+        let mut template = original;
+        template.parts[1].sequence[2].set_locked(StepMask(0xF0F0_F0F0));
+        template.mutate(1.0, 5);
+        assert!(template.validate().is_ok());
+        assert_ne!(template.parts[0], original.parts[0]);
+        for step in (0..STEP_COUNT).filter(|step| template.parts[1].sequence[2].locked.contains(*step)) {
+            assert_eq!(template.parts[1].sequence[2].steps[step], original.parts[1].sequence[2].steps[step]);
+        }
+
+        let mut again = original;
+        again.parts[1].sequence[2].set_locked(StepMask(0xF0F0_F0F0));
+        again.mutate(1.0, 5);
+        assert_eq!(again, template);
+
+        let mut untouched = original;
+        untouched.mutate(0.0, 5);
+        assert_eq!(untouched, original);
+    }
+
+    #[test]
+    fn mutate_leaves_empty_steps_empty_unless_a_hit_moves_there() {
+        let mut sequence = DrumGenSequence::new();
+        sequence.set_step(8, 10);
+        for seed in 0..20 {
+            let mut mutated = sequence;
+            mutated.mutate(1.0, seed);
+            let hits: Vec<usize> = (0..STEP_COUNT).filter(|i| mutated.steps[*i] > 0).collect();
+            assert!(hits.is_empty() || (hits.len() == 1 && (7..=9).contains(&hits[0])), "{:?}", hits);
+        }
+        let mut layer = DrumGenLayer::new();
+        layer.sequence[1] = sequence;
+        let mut mutated = layer;
+        mutated.mutate(1.0, 3);
+        assert_eq!(mutated.sequence[0], DrumGenSequence::new());
+    }
+
+    #[test]
+    fn repeat_tiles_a_period() {
+        let mut sequence = ramp();
+        sequence.repeat(2, 3, STEP_COUNT);
+        for step in 2..STEP_COUNT {
+            assert_eq!(sequence.steps[step], [2, 3, 4][(step - 2) % 3], "step {}", step);
+        }
+        assert_eq!(&sequence.steps[..2], &[0, 1]);
+
+        let mut sequence = ramp();
+        sequence.repeat(4, 4, 1);
+        assert_eq!(&sequence.steps[..13], &[0, 1, 2, 3, 4, 5, 6, 7, 4, 5, 6, 7, 1]);
+
+        // Periods running past the end are cut off, and nothing happens without a period.
+        let mut sequence = ramp();
+        sequence.repeat(28, 8, STEP_COUNT);
+        sequence.repeat(0, 0, STEP_COUNT);
+        sequence.repeat(40, 2, 1);
+        assert_eq!(sequence, ramp());
+        sequence.repeat(20, 8, STEP_COUNT);
+        assert_eq!(&sequence.steps[28..], &ramp().steps[20..24]);
+    }
+
+    #[test]
+    fn bulk_edits_leave_locked_steps_alone() {
+        let mut sequence = ramp();
+        for step in [0, 4, 9] {
+            sequence.locked.set(step, true);
+        }
+        let locked_values = |sequence: &DrumGenSequence| [0, 4, 9].map(|step| sequence.steps[step]);
+        let before = locked_values(&sequence);
+
+        sequence.shift(1);
+        assert_eq!(&sequence.steps[..10], &[0, 2, 3, 5, 4, 6, 7, 8, 10, 9]);
+        sequence.shift(-1);
+        assert_eq!(sequence, { let mut ramp = ramp(); ramp.set_locked(sequence.locked()); ramp });
+
+        sequence.randomize(10, 10, 100, &mut StdRng::seed_from_u64(0));
+        sequence.add(-3);
+        sequence.scale_range(0..STEP_COUNT, 0.5);
+        sequence.repeat(0, 3, STEP_COUNT);
+        sequence.clear();
+        assert_eq!(locked_values(&sequence), before);
+        assert!((0..STEP_COUNT).filter(|step| !sequence.locked.contains(*step)).all(|step| sequence.steps[step] == 0));
+
+        sequence.set_step(4, 10);
+        assert_eq!(sequence.steps[4], 10);
+    }
+
+    #[test]
+    fn ranges_past_the_last_step_are_cut_off() {
+        let mut sequence = ramp();
+        sequence.add_range(30..40, 1);
+        assert_eq!(&sequence.steps[28..], &[6, 7, 9, 10]);
+        sequence.shift_range(30..40, 1);
+        assert_eq!(&sequence.steps[28..], &[6, 7, 10, 9]);
+        sequence.scale_range(31..99, 0.0);
+        sequence.set_range(40..50, 3);
+        sequence.randomize_range(20..usize::MAX, 1, 1, 100, &mut StdRng::seed_from_u64(0));
+        assert_eq!(&sequence.steps[28..], &[1, 1, 1, 1]);
+        sequence.clear_range(Range { start: 8, end: 4 });
+        assert_eq!(sequence.steps[4..8], ramp().steps[4..8]);
+    }
+
+    #[test]
+    fn step_mask_sets_and_clears_steps() {
+        let mut mask = StepMask::NONE;
+        assert!(mask.is_empty());
+        mask.set(0, true);
+        mask.toggle(31);
+        mask.set(40, true);
+        assert_eq!(mask, StepMask(0x8000_0001));
+        assert!(mask.contains(31) && !mask.contains(1) && !mask.contains(40));
+        mask.toggle(0);
+        assert_eq!(mask, StepMask(1 << 31));
+    }
+}
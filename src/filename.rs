@@ -0,0 +1,115 @@
+/*
+    NGEN file name rules.
+
+    NGEN reads templates from a FAT formatted SD card and only accepts short
+    8.3 file names: a name of 1-8 letters, digits, `_` or `-` followed by the
+    .hex extension. Letter case doesn't matter on the card; names suggested
+    here are uppercase like the ones NGEN ships with (DGT_0001.HEX).
+*/
+use crate::drumgen::DrumGenError;
+use std::path::Path;
+
+/// Longest template name NGEN accepts (the 8 of an 8.3 file name).
+pub const MAX_NAME_LENGTH: usize = 8;
+
+/// Extension of template files.
+pub const EXTENSION: &str = "hex";
+
+// Name used when nothing usable is left of a suggested name.
+const FALLBACK_NAME: &str = "DGT_0001";
+
+fn is_allowed(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Check a template name (the part before .hex).
+pub fn validate_name(name: &str) -> Result<(), DrumGenError> {
+    if name.is_empty() {
+        return Err(DrumGenError::InvalidName("the name is empty".to_string()));
+    }
+    if let Some(c) = name.chars().find(|c| !is_allowed(*c)) {
+        return Err(DrumGenError::InvalidName(format!(
+            "`{}` contains `{}` (use letters, digits, _ or -)",
+            name, c
+        )));
+    }
+    let length = name.chars().count();
+    if length > MAX_NAME_LENGTH {
+        return Err(DrumGenError::InvalidName(format!(
+            "`{}` is {} characters long (at most {})",
+            name, length, MAX_NAME_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+/// Check that a path ends in a file name NGEN can load, e.g. DGT_0001.HEX.
+pub fn validate_file_name<P: AsRef<Path>>(path: P) -> Result<(), DrumGenError> {
+    let path = path.as_ref();
+    let file_name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    match file_name.rsplit_once('.') {
+        Some((name, extension)) if extension.eq_ignore_ascii_case(EXTENSION) => validate_name(name),
+        _ => Err(DrumGenError::InvalidName(format!("`{}` does not end in .hex", file_name))),
+    }
+}
+
+/// Turn any text into a valid, uppercase template name.
+///
+/// Spaces and other characters NGEN doesn't accept become `_`, and the
+/// result is cut to 8 characters.
+pub fn suggest_name(name: &str) -> String {
+    let name: String = name
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if is_allowed(c) { c.to_ascii_uppercase() } else { '_' })
+        .take(MAX_NAME_LENGTH)
+        .collect();
+    if name.chars().all(|c| c == '_') {
+        FALLBACK_NAME.to_string()
+    } else {
+        name
+    }
+}
+
+/// [`suggest_name`] with the .HEX extension.
+pub fn suggest_file_name(name: &str) -> String {
+    format!("{}.{}", suggest_name(name), EXTENSION.to_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_names() {
+        for name in ["DGT_0001", "KICKS", "a-b", "X"] {
+            assert!(validate_name(name).is_ok(), "{}", name);
+        }
+        for name in ["", "TOOLONGNM", "MY KICK", "DRUM.01", "ÄPFEL"] {
+            assert!(matches!(validate_name(name), Err(DrumGenError::InvalidName(_))), "{}", name);
+        }
+    }
+
+    #[test]
+    fn validates_file_names() {
+        assert!(validate_file_name("/DRUMGEN/DGT_0001.HEX").is_ok());
+        assert!(validate_file_name("kicks.hex").is_ok());
+        assert!(validate_file_name("my long template.hex").is_err());
+        assert!(validate_file_name("DGT_0001.txt").is_err());
+        assert!(validate_file_name("DGT_0001").is_err());
+    }
+
+    #[test]
+    fn suggestions_are_valid() {
+        assert_eq!(suggest_name("my long template"), "MY_LONG_");
+        assert_eq!(suggest_name(" kicks "), "KICKS");
+        assert_eq!(suggest_name("Drum.01"), "DRUM_01");
+        assert_eq!(suggest_name("   "), FALLBACK_NAME);
+        assert_eq!(suggest_name("äöü"), FALLBACK_NAME);
+        assert_eq!(suggest_file_name("dgt_0002"), "DGT_0002.HEX");
+        for name in ["my long template", "Drum.01", "", "ÄPFEL 2"] {
+            assert!(validate_file_name(suggest_file_name(name)).is_ok(), "{}", name);
+        }
+    }
+}
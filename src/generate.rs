@@ -0,0 +1,291 @@
+/*
+    Pattern generators.
+
+    Structured starting points for templates, as opposed to the purely random
+    values of DrumGenSequence::randomize.
+
+    Style presets assume the usual kit layout of the four parts: kick, snare
+    or clap, closed hats and a percussion / open hat part.
+*/
+use crate::drumgen::{clamp_steps, DrumGen, DrumGenSequence, MAX_STEP_VALUE, STEP_COUNT};
+use crate::text::parse_steps;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::ops::Range;
+
+/// A Euclidean rhythm: `pulses` hits spread as evenly as possible over `steps` steps.
+///
+/// Hits get the `on_value` probability and the other steps `off_value`.
+/// Patterns shorter than the sequence repeat, so E(3, 8) fills the 32 steps
+/// with four bars of the tresillo.
+///
+/// ```
+/// use drumgen::{DrumGenSequence, Euclidean};
+///
+/// let tresillo = Euclidean { pulses: 3, steps: 8, ..Euclidean::default() };
+/// assert_eq!(tresillo.to_string(), "x..x..x.");
+///
+/// let mut sequence = DrumGenSequence::new();
+/// tresillo.apply(&mut sequence);
+/// assert_eq!(&sequence.steps[..8], &[10, 0, 0, 10, 0, 0, 10, 0]);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Euclidean {
+    /// Number of hits, at most `steps`.
+    pub pulses: usize,
+    /// Length of the pattern, 1 - 32.
+    pub steps: usize,
+    /// Number of steps the pattern is moved to the right.
+    pub rotation: usize,
+    /// Probability of the hits (0 - 10).
+    pub on_value: u8,
+    /// Probability of the other steps (0 - 10).
+    pub off_value: u8,
+}
+
+impl Default for Euclidean {
+    fn default() -> Self {
+        Euclidean { pulses: 4, steps: 16, rotation: 0, on_value: MAX_STEP_VALUE, off_value: 0 }
+    }
+}
+
+impl Euclidean {
+    /// Length of the pattern, clamped to 1 - 32.
+    pub fn length(&self) -> usize {
+        self.steps.clamp(1, STEP_COUNT)
+    }
+
+    /// Whether each step of the pattern is a hit.
+    pub fn hits(&self) -> Vec<bool> {
+        let steps = self.length();
+        let pulses = self.pulses.min(steps);
+        let rotation = self.rotation % steps;
+        (0..steps)
+            .map(|step| (step + steps - rotation) % steps * pulses % steps < pulses)
+            .collect()
+    }
+
+    /// Probability of each step of the pattern.
+    pub fn values(&self) -> Vec<u8> {
+        self.hits()
+            .into_iter()
+            .map(|hit| if hit { self.on_value } else { self.off_value }.min(MAX_STEP_VALUE))
+            .collect()
+    }
+
+    /// Fill a sequence with the rhythm, repeating patterns shorter than 32 steps.
+    /// Locked steps are left alone.
+    pub fn apply(&self, sequence: &mut DrumGenSequence) {
+        self.apply_range(sequence, 0..STEP_COUNT);
+    }
+
+    /// [`apply`](Self::apply) limited to a range of steps. The pattern starts at the first step of the range.
+    pub fn apply_range(&self, sequence: &mut DrumGenSequence, steps: Range<usize>) {
+        let values = self.values();
+        for (i, step) in clamp_steps(steps).enumerate() {
+            sequence.set_unlocked(step, values[i % values.len()]);
+        }
+    }
+}
+
+impl std::fmt::Display for Euclidean {
+    /// The pattern with `x` for hits and `.` for the other steps.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for hit in self.hits() {
+            f.write_str(if hit { "x" } else { "." })?;
+        }
+        Ok(())
+    }
+}
+
+/// A style a complete template can be generated from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Style {
+    FourOnTheFloor,
+    Breakbeat,
+    TrapHats,
+    HalfTime,
+}
+
+// Steps of each layer (127, 100, 60) of each part, written like the text format:
+// one character per step, `A` for 10, spaces ignored. Shorter shapes repeat.
+type Shape = [[&'static str; 3]; 4];
+
+const FOUR_ON_THE_FLOOR: Shape = [
+    ["A000 A000 A000 A000", "0000 0000 0000 0000", "0000 0000 0020 0000"],
+    ["0000 A000 0000 A000", "0000 0000 0000 0000", "0000 0000 0000 0020"],
+    ["00A0 00A0 00A0 00A0", "0000 0000 0000 0000", "0505 0505 0505 0505"],
+    ["0000 0000 0000 0000", "0030 0000 0030 0000", "0000 0200 0000 0020"],
+];
+
+const BREAKBEAT: Shape = [
+    ["A0A0 0000 00A0 0000", "0000 0000 0000 0050", "0000 0030 0000 0000"],
+    ["0000 A000 0000 A000", "0000 0006 0600 0000", "0020 0020 0020 0002"],
+    ["A0A0 A0A0 A0A0 A0A0", "0000 0000 0000 0000", "0303 0303 0303 0303"],
+    ["0000 0000 0000 0000", "0000 0000 0000 0080", "0000 0000 0000 0000"],
+];
+
+const TRAP_HATS: Shape = [
+    ["A000 0000 00A0 0000 0000 00A0 A000 0000", "0000 0000 0000 0000 0000 0000 0000 0050", "0000 0000 0000 0000 0000 0000 0000 0000"],
+    ["0000 0000 A000 0000 0000 0000 A000 0000", "0000 0000 0000 0000 0000 0000 0000 0000", "0000 0000 0000 0030 0000 0000 0000 0003"],
+    ["A0A0 A0A0 A0A0 A0A0 A0A0 A0A0 A0A0 A0A0", "0303 0303 0303 0606 0303 0303 0303 0A0A", "0000 0000 0000 0000 0000 0000 0000 0000"],
+    ["0000 0000 0000 0000 0000 0000 0000 0000", "0000 0000 0000 0000 0000 0000 0000 0070", "0000 0000 0000 0000 0000 0000 0000 0000"],
+];
+
+const HALF_TIME: Shape = [
+    ["A000 0000 0000 0000 A000 00A0 0000 0000", "0000 0000 0000 0040 0000 0000 0000 0000", "0000 0000 0000 0000 0000 0000 0000 0000"],
+    ["0000 0000 A000 0000 0000 0000 A000 0000", "0000 0000 0000 0000 0000 0000 0000 0000", "0000 0000 0000 0020 0000 0000 0000 0302"],
+    ["A000 A000 A000 A000", "0060 0060 0060 0060", "0303 0303 0303 0303"],
+    ["0000 0000 0000 0000 0000 0000 0000 0000", "0000 0000 0000 0000 0000 0000 0000 0050", "0000 0000 0000 0000 0000 0000 0000 0000"],
+];
+
+// Values of a shape, repeated to 32 steps.
+fn shape_steps(shape: &str) -> [u8; STEP_COUNT] {
+    let values = parse_steps(0, shape).expect("preset shapes use the text format");
+    let mut steps = [0; STEP_COUNT];
+    for (i, step) in steps.iter_mut().enumerate() {
+        *step = values[i % values.len()];
+    }
+    steps
+}
+
+impl Style {
+    pub const ALL: [Style; 4] = [Style::FourOnTheFloor, Style::Breakbeat, Style::TrapHats, Style::HalfTime];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Style::FourOnTheFloor => "Four-on-the-floor",
+            Style::Breakbeat => "Breakbeat",
+            Style::TrapHats => "Trap hats",
+            Style::HalfTime => "Half-time",
+        }
+    }
+
+    fn shape(&self) -> &'static Shape {
+        match self {
+            Style::FourOnTheFloor => &FOUR_ON_THE_FLOOR,
+            Style::Breakbeat => &BREAKBEAT,
+            Style::TrapHats => &TRAP_HATS,
+            Style::HalfTime => &HALF_TIME,
+        }
+    }
+
+    /// Generate a template in this style.
+    ///
+    /// `variation` (0.0 - 1.0) is the chance of each step moving away from the
+    /// preset: steps that can play are nudged up or down, and empty steps of the
+    /// quietest layer may get a ghost note. The loudest hits of the preset are
+    /// never removed. The same style, variation and seed always give the same template.
+    pub fn generate(&self, variation: f32, seed: u64) -> DrumGen {
+        let variation = variation.clamp(0.0, 1.0);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut template = DrumGen::new();
+        for (part, shapes) in template.parts.iter_mut().zip(self.shape()) {
+            for (layer, (sequence, shape)) in part.sequence.iter_mut().zip(shapes).enumerate() {
+                sequence.steps = shape_steps(shape);
+                for step in sequence.steps.iter_mut() {
+                    if !rng.gen_bool(variation as f64) {
+                        continue;
+                    }
+                    if *step > 0 {
+                        let lowest = if layer == 0 && *step == MAX_STEP_VALUE { MAX_STEP_VALUE - 2 } else { 1 };
+                        *step = (*step as i8 + rng.gen_range(-2..=2)).clamp(lowest as i8, MAX_STEP_VALUE as i8) as u8;
+                    } else if layer == 2 && rng.gen_bool(0.25) {
+                        *step = rng.gen_range(1..=3);
+                    }
+                }
+            }
+        }
+        template
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drumgen::StepMask;
+
+    fn pattern(pulses: usize, steps: usize, rotation: usize) -> String {
+        Euclidean { pulses, steps, rotation, ..Euclidean::default() }.to_string()
+    }
+
+    #[test]
+    fn spreads_pulses_evenly() {
+        assert_eq!(pattern(4, 16, 0), "x...x...x...x...");
+        assert_eq!(pattern(5, 8, 0), "x.x.xx.x");
+        assert_eq!(pattern(0, 4, 0), "....");
+        assert_eq!(pattern(9, 4, 0), "xxxx");
+        for pulses in 0..=STEP_COUNT {
+            let hits = Euclidean { pulses, steps: STEP_COUNT, ..Euclidean::default() }.hits();
+            assert_eq!(hits.iter().filter(|hit| **hit).count(), pulses);
+        }
+    }
+
+    #[test]
+    fn rotation_moves_the_pattern_right() {
+        assert_eq!(pattern(3, 8, 1), ".x..x..x");
+        assert_eq!(pattern(3, 8, 9), pattern(3, 8, 1));
+    }
+
+    #[test]
+    fn length_is_clamped() {
+        assert_eq!(Euclidean { steps: 0, ..Euclidean::default() }.length(), 1);
+        assert_eq!(Euclidean { steps: 64, ..Euclidean::default() }.length(), STEP_COUNT);
+        let values = Euclidean { pulses: 1, steps: 2, on_value: 12, off_value: 3, rotation: 0 }.values();
+        assert_eq!(values, [MAX_STEP_VALUE, 3]);
+    }
+
+    #[test]
+    fn apply_repeats_and_skips_locked_steps() {
+        let rhythm = Euclidean { pulses: 3, steps: 8, rotation: 0, on_value: 9, off_value: 2 };
+        let mut sequence = DrumGenSequence::new();
+        rhythm.apply(&mut sequence);
+        for bar in sequence.steps.chunks(8) {
+            assert_eq!(bar, &[9, 2, 2, 9, 2, 2, 9, 2]);
+        }
+
+        let mut sequence = DrumGenSequence::new();
+        sequence.steps[..8].copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        sequence.set_locked(StepMask(1 << 5));
+        rhythm.apply_range(&mut sequence, 4..7);
+        assert_eq!(&sequence.steps[..8], &[0, 1, 2, 3, 9, 5, 2, 7]);
+        rhythm.apply_range(&mut sequence, 30..40);
+        assert_eq!(&sequence.steps[30..], &[9, 2]);
+    }
+
+    #[test]
+    fn presets_without_variation_follow_their_shape() {
+        let template = Style::FourOnTheFloor.generate(0.0, 1);
+        for (i, step) in template.parts[0].sequence[0].steps.iter().enumerate() {
+            assert_eq!(*step, if i % 4 == 0 { 10 } else { 0 }, "step {}", i);
+        }
+        for style in Style::ALL {
+            let shape = style.shape();
+            for part in shape {
+                for layer in part {
+                    let length = layer.chars().filter(|c| !c.is_whitespace()).count();
+                    assert!(length == 16 || length == STEP_COUNT, "{}: {}", style.name(), layer);
+                }
+            }
+            assert!(style.generate(0.0, 1).validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn variation_is_seeded_and_keeps_the_main_hits() {
+        for style in Style::ALL {
+            let a = style.generate(0.5, 42);
+            assert_eq!(a, style.generate(0.5, 42));
+            assert_ne!(a, style.generate(0.5, 43));
+            assert!(a.validate().is_ok());
+            let plain = style.generate(0.0, 0);
+            for (part, plain_part) in a.parts.iter().zip(plain.parts.iter()) {
+                for (step, plain_step) in part.sequence[0].steps.iter().zip(plain_part.sequence[0].steps) {
+                    if plain_step == MAX_STEP_VALUE {
+                        assert!(*step >= MAX_STEP_VALUE - 2);
+                    }
+                }
+            }
+        }
+    }
+}
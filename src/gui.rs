@@ -1,6 +1,9 @@
-#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 use eframe::egui;
-use crate::drumgen::*;
+use drumgen::{DrumGen, DrumGenError, DrumGenLayer, DrumGenProject, DrumGenSequence, Euclidean, Library, ProjectFormat, StepClip, Style, TemplateMetadata, STEP_COUNT};
+use drumgen::filename::{suggest_file_name, suggest_name, validate_file_name, validate_name};
+use drumgen::midi::{learn_template, MidiExportSettings, MidiLearnSettings};
+use crate::document::Document;
+use crate::step_grid::{self, StepGrid, Tool};
 use egui::*;
 use egui::special_emojis::GITHUB;
 use egui::style::HandleShape;
@@ -9,6 +12,12 @@ use egui::style::HandleShape;
 use simplelog::*;
 #[cfg(feature="logging")]
 use std::fs::File;
+use std::ops::Range;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::path::{Path, PathBuf};
+
+const APP_TITLE: &str = "NGEN – DrumGen Template Editor";
 
 
 /*
@@ -34,7 +43,7 @@ pub fn main_gui() -> Result<(), eframe::Error> {
     };
 
     eframe::run_native(
-        "NGEN – DrumGen Template Editor",
+        APP_TITLE,
         options,
         Box::new(|_cc| {
             Box::<DrumgenEditor>::default()
@@ -53,43 +62,686 @@ pub fn main_gui() -> Result<(), eframe::Error> {
 */
 
 pub struct DrumgenEditor {
-    template: DrumGen,
-    name: String,
+    documents: Vec<Document>,
+    active_document: usize,
     active_part: usize,
     value_display: String,
     output: Vec<u8>,
     random_min: u8,
     random_max: u8,
     random_probability: u8,
+    // Seed of the next randomization, and the one used last.
+    random_seed: u64,
+    last_random_seed: Option<u64>,
     dropped_files: Vec<egui::DroppedFile>,
-    picked_path: Option<String>,
+    error_message: Option<String>,
+    show_metadata: bool,
+    midi_settings: MidiExportSettings,
+    show_midi_export: bool,
+    learn_notes: [String; 4],
+    show_midi_learn: bool,
+    pending_action: Option<PendingAction>,
+    // Action to run once the file name dialog has saved the file it was asked to save first.
+    after_save: Option<PendingAction>,
+    allow_close: bool,
+    window_title: String,
+    show_copy: bool,
+    copy_source: usize,
+    // Last steps copied in the editor, for the Paste buttons: egui only hands
+    // out the system clipboard with a Ctrl+V paste event.
+    copied_steps: Option<String>,
+    show_morph: bool,
+    // Tab the current template is morphed towards.
+    morph_target: usize,
+    morph_amount: f32,
+    morph_crossfade: bool,
+    morph_seed: u64,
+    show_variations: bool,
+    variation_count: u32,
+    variation_amount: f32,
+    variation_seed: u64,
+    variation_overwrite: bool,
+    copy_from_part: usize,
+    copy_to_part: usize,
+    // None copies the whole part.
+    copy_layer: Option<usize>,
+    show_library: bool,
+    library: Option<Library>,
+    // Entry being renamed and the name typed so far.
+    library_rename: Option<(usize, String)>,
+    // Entry waiting for the user to confirm deletion.
+    library_delete: Option<usize>,
+    // Path chosen for saving that NGEN would reject, and why.
+    name_warning: Option<(String, String)>,
+    selection: Option<Selection>,
+    set_value: u8,
+    scale_percent: u32,
+    tool: Tool,
+    // Shape of the curve tool, -1.0 to 1.0.
+    curve_bend: f32,
+    // Period drawing on the grid repeats with, 0 for off.
+    repeat_every: usize,
+    // Layer the Euclidean window is open for.
+    euclidean_layer: Option<usize>,
+    euclidean: Euclidean,
+    show_preset: bool,
+    preset_style: Style,
+    preset_variation: f32,
+    preset_seed: u64,
+}
+
+// Rectangle of steps across neighbouring layers, from the step where the selection
+// started (anchor) to the one where it ends (focus). Both are (layer, step).
+#[derive(Clone, Copy, PartialEq)]
+struct Selection {
+    anchor: (usize, usize),
+    focus: (usize, usize),
+}
+
+impl Selection {
+    fn layers(&self) -> Range<usize> {
+        self.anchor.0.min(self.focus.0)..self.anchor.0.max(self.focus.0) + 1
+    }
+
+    fn steps(&self) -> Range<usize> {
+        self.anchor.1.min(self.focus.1)..self.anchor.1.max(self.focus.1) + 1
+    }
+}
+
+// Actions that replace or close a template and need confirmation while there are unsaved changes.
+enum PendingAction {
+    Open,
+    OpenPath(PathBuf),
+    ImportText,
+    ImportProject,
+    LearnMidi,
+    CloseTab(usize),
+    Close,
+}
+
+// Something the user did in the library window.
+enum LibraryAction {
+    ChooseFolder,
+    Refresh,
+    Open(usize),
+    Rename(usize, String),
+    Duplicate(usize),
+    Delete(usize),
+    Swap(usize, usize),
 }
 
 impl DrumgenEditor {
-    fn get_filename(&self) -> String {
-        format!("{}.hex", self.name).to_uppercase()
+    fn doc(&self) -> &Document {
+        &self.documents[self.active_document]
+    }
+
+    fn doc_mut(&mut self) -> &mut Document {
+        &mut self.documents[self.active_document]
+    }
+
+    fn window_title(&self) -> String {
+        format!("{} – {}", self.doc().title(), APP_TITLE)
+    }
+
+    // First DGT_xxxx name that no open tab uses.
+    fn next_name(&self) -> String {
+        (1..)
+            .map(|n| format!("DGT_{:04}", n))
+            .find(|name| self.documents.iter().all(|document| document.name != *name))
+            .unwrap_or_default()
+    }
+
+    fn new_tab(&mut self) {
+        let document = Document::new(&self.next_name());
+        self.documents.push(document);
+        self.active_document = self.documents.len() - 1;
+    }
+
+    fn close_tab(&mut self, index: usize) {
+        self.documents.remove(index);
+        if self.documents.is_empty() {
+            self.documents.push(Document::new("DGT_0001"));
+        }
+        for selected in [&mut self.active_document, &mut self.copy_source, &mut self.morph_target] {
+            if *selected > index || *selected == self.documents.len() {
+                *selected -= 1;
+            }
+        }
+    }
+
+    // Run the action right away, or ask first if it would discard unsaved changes.
+    fn request(&mut self, ctx: &egui::Context, action: PendingAction) {
+        if let PendingAction::CloseTab(index) = action {
+            self.active_document = index;
+        }
+        if matches!(action, PendingAction::Close) || !self.doc().is_dirty() {
+            self.run(ctx, action);
+        } else {
+            self.pending_action = Some(action);
+        }
+    }
+
+    fn run(&mut self, ctx: &egui::Context, action: PendingAction) {
+        match action {
+            PendingAction::Open => {
+                if let Some(path) = rfd::FileDialog::new().pick_file() {
+                    self.open_file(&path);
+                }
+            }
+            PendingAction::OpenPath(path) => self.open_file(&path),
+            PendingAction::ImportText => {
+                if let Some(path) = rfd::FileDialog::new().add_filter("Text template", &["txt"]).pick_file() {
+                    self.import_text(&path);
+                }
+            }
+            PendingAction::ImportProject => {
+                if let Some(path) = rfd::FileDialog::new().add_filter("Project", &ProjectFormat::enabled_extensions()).pick_file() {
+                    self.import_project(&path);
+                }
+            }
+            PendingAction::LearnMidi => {
+                if let Some(paths) = rfd::FileDialog::new().add_filter("MIDI file", &["mid", "midi"]).pick_files() {
+                    self.learn_from_midi(&paths);
+                }
+            }
+            PendingAction::CloseTab(index) => self.close_tab(index),
+            PendingAction::Close => {
+                // Ask about each tab with unsaved changes in turn.
+                if let Some(index) = self.documents.iter().position(Document::is_dirty) {
+                    self.active_document = index;
+                    self.pending_action = Some(PendingAction::Close);
+                } else {
+                    self.allow_close = true;
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+            }
+        }
+    }
+
+    // Save to the current path, asking for one if the template has none. Returns true if saved.
+    fn save(&mut self) -> bool {
+        match self.doc().picked_path.clone() {
+            Some(path) => self.save_file(path),
+            None => self.save_as(),
+        }
+    }
+
+    fn save_as(&mut self) -> bool {
+        match rfd::FileDialog::new().set_file_name(self.doc().get_filename()).save_file() {
+            Some(path) => self.save_file(path.display().to_string()),
+            None => false,
+        }
+    }
+
+    // Show an error, below the ones still shown so none of them gets lost.
+    fn report_error(&mut self, message: String) {
+        self.error_message = Some(match self.error_message.take() {
+            Some(shown) => format!("{}\n\n{}", shown, message),
+            None => message,
+        });
+    }
+
+    // Check the dropped files. The first valid template replaces the current tab, the others open in new tabs.
+    fn open_dropped_files(&mut self, ctx: &egui::Context, files: Vec<egui::DroppedFile>) {
+        let mut errors = Vec::new();
+        let mut valid = Vec::new();
+        for file in files {
+            let Some(path) = file.path else {
+                errors.push(format!("{}: not a file on disk", file.name));
+                continue;
+            };
+            match DrumGen::new().parse_file(&path) {
+                Ok(()) => valid.push(path),
+                Err(err) => errors.push(format!("{}: {}", path.display(), err)),
+            }
+        }
+        if !errors.is_empty() {
+            log::error!("Dropped files are not valid templates: {}", errors.join("; "));
+            self.report_error(format!(
+                "These files are not valid 192 byte DrumGen templates:\n\n{}",
+                errors.join("\n")
+            ));
+        }
+        let mut paths = valid.into_iter();
+        let Some(first) = paths.next() else {
+            return;
+        };
+        let active = self.active_document;
+        for path in paths {
+            self.new_tab();
+            self.open_file(&path);
+        }
+        self.active_document = active;
+        self.request(ctx, PendingAction::OpenPath(first));
+    }
+
+    fn open_file(&mut self, path: &std::path::Path) {
+        match self.doc_mut().open_file(path) {
+            Ok(()) => {
+                // The status bar shows the warning while the tab has this path.
+                if let Err(err) = validate_file_name(path) {
+                    log::warn!("{} has a file name NGEN can't load: {}", path.display(), err);
+                }
+            }
+            Err(err) => {
+                log::error!("Failed to open {}: {}", path.display(), err);
+                self.report_error(format!("Could not open {}:\n{}", path.display(), err));
+            }
+        }
+    }
+
+    // Save to a path, first asking what to do if NGEN would reject its file name. Returns true if saved.
+    fn save_file(&mut self, path: String) -> bool {
+        if let Err(err) = validate_file_name(&path) {
+            self.name_warning = Some((path, err.to_string()));
+            return false;
+        }
+        self.write_file(path)
+    }
+
+    fn write_file(&mut self, path: String) -> bool {
+        match self.doc_mut().save_file(&path) {
+            Ok(()) => {
+                // Show templates saved into the library folder right away.
+                if let Some(library) = self.library.as_mut().filter(|library| Path::new(&path).parent() == Some(library.dir.as_path())) {
+                    let _ = library.refresh();
+                }
+                true
+            }
+            Err(err) => {
+                log::error!("Failed to save {}: {}", path, err);
+                self.report_error(format!("Could not save {}:\n{}", path, err));
+                false
+            }
+        }
+    }
+
+    // Write mutated copies of the current template to DGT_0001.HEX, DGT_0002.HEX, ... in a folder.
+    fn write_variations(&mut self, dir: &Path) {
+        let paths: Vec<PathBuf> = (1..=self.variation_count)
+            .map(|number| dir.join(suggest_file_name(&format!("DGT_{:04}", number))))
+            .collect();
+        if !self.variation_overwrite {
+            let existing: Vec<String> = paths.iter()
+                .filter(|path| path.exists())
+                .filter_map(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+                .collect();
+            if !existing.is_empty() {
+                self.report_error(format!("{} already has {}.\nTick Overwrite to replace them.", dir.display(), existing.join(", ")));
+                return;
+            }
+        }
+        for (index, path) in paths.iter().enumerate() {
+            let mut template = self.doc().template;
+            template.mutate(self.variation_amount, self.variation_seed.wrapping_add(index as u64));
+            if let Err(err) = template.save_file(path) {
+                log::error!("Failed to save {}: {}", path.display(), err);
+                self.report_error(format!("Could not save {}:\n{}", path.display(), err));
+                return;
+            }
+        }
+        log::info!("Wrote {} variations to {}", paths.len(), dir.display());
+        if let Some(library) = self.library.as_mut().filter(|library| library.dir == dir) {
+            let _ = library.refresh();
+        }
+    }
+
+    fn import_text(&mut self, path: &std::path::Path) {
+        let result = std::fs::read_to_string(path)
+            .map_err(DrumGenError::from)
+            .and_then(|text| DrumGen::from_text(&text));
+        match result {
+            Ok((template, name)) => {
+                // The imported text file is not a .hex file, so the next Save asks for a path.
+                let name = name.or_else(|| path.file_stem().map(|stem| stem.to_string_lossy().to_string()));
+                self.doc_mut().load(template, name, None, TemplateMetadata::default());
+            }
+            Err(err) => {
+                log::error!("Failed to import {}: {}", path.display(), err);
+                self.report_error(format!("Could not import {}:\n{}", path.display(), err));
+            }
+        }
+    }
+
+    fn import_project(&mut self, path: &std::path::Path) {
+        match DrumGenProject::parse_file(path) {
+            Ok(project) => {
+                // Project files are not .hex files, so the next Save asks for a path.
+                let name = Some(project.metadata.name.clone()).filter(|name| !name.is_empty());
+                self.doc_mut().load(project.template, name, None, project.metadata);
+            }
+            Err(err) => {
+                log::error!("Failed to import {}: {}", path.display(), err);
+                self.report_error(format!("Could not import {}:\n{}", path.display(), err));
+            }
+        }
+    }
+
+    fn export_project(&mut self, path: &std::path::Path) {
+        let project = self.doc_mut().project();
+        if let Err(err) = project.save_file(path) {
+            log::error!("Failed to export {}: {}", path.display(), err);
+            self.report_error(format!("Could not export {}:\n{}", path.display(), err));
+        }
+    }
+
+    fn export_midi(&mut self, path: &std::path::Path) {
+        self.midi_settings.velocities = self.doc().metadata.velocities;
+        if let Err(err) = self.doc().template.save_midi_file(path, &self.midi_settings) {
+            log::error!("Failed to export {}: {}", path.display(), err);
+            self.report_error(format!("Could not export {}:\n{}", path.display(), err));
+        }
+    }
+
+    fn learn_from_midi(&mut self, paths: &[std::path::PathBuf]) {
+        let mut settings = MidiLearnSettings { velocities: self.doc().metadata.velocities, ..Default::default() };
+        for (notes, input) in settings.notes.iter_mut().zip(self.learn_notes.iter()) {
+            match parse_note_list(input) {
+                Some(parsed) => *notes = parsed,
+                None => {
+                    self.report_error(format!("Invalid note list \"{}\": use MIDI notes 0-127 separated by commas.", input));
+                    return;
+                }
+            }
+        }
+        match learn_template(paths, &settings) {
+            Ok(template) => {
+                // The learned template has no .hex file yet, so the next Save asks for a path.
+                let metadata = self.doc().metadata.clone();
+                self.doc_mut().load(template, None, None, metadata);
+            }
+            Err(err) => {
+                log::error!("Failed to learn from MIDI files: {}", err);
+                self.report_error(format!("Could not learn from the MIDI files:\n{}", err));
+            }
+        }
+    }
+
+    fn export_text(&mut self, path: &std::path::Path) {
+        let document = self.doc();
+        if let Err(err) = std::fs::write(path, document.template.to_text(Some(&document.name))) {
+            log::error!("Failed to export {}: {}", path.display(), err);
+            self.report_error(format!("Could not export {}:\n{}", path.display(), err));
+        }
+    }
+
+    // Switch to the tab showing a file, or open it in a new tab.
+    // An untouched empty tab is reused instead of leaving it behind.
+    fn open_in_tab(&mut self, path: &Path) {
+        let path_text = path.display().to_string();
+        if let Some(index) = self.documents.iter().position(|document| document.picked_path.as_deref() == Some(path_text.as_str())) {
+            self.active_document = index;
+            return;
+        }
+        let mut document = Document::new(&self.next_name());
+        if let Err(err) = document.open_file(path) {
+            log::error!("Failed to open {}: {}", path.display(), err);
+            self.report_error(format!("Could not open {}:\n{}", path.display(), err));
+            return;
+        }
+        self.add_document(document);
+    }
+
+    // Show a document in the current tab if that one is still untouched, or in a new tab.
+    fn add_document(&mut self, document: Document) {
+        let current = self.doc();
+        if current.picked_path.is_none() && !current.is_dirty() && current.template == DrumGen::new() {
+            *self.doc_mut() = document;
+        } else {
+            self.documents.push(document);
+            self.active_document = self.documents.len() - 1;
+        }
+    }
+
+    fn new_from_preset(&mut self) {
+        let mut document = Document::new(&self.next_name());
+        document.template = self.preset_style.generate(self.preset_variation, self.preset_seed);
+        log::info!("New template from preset {} (seed {})", self.preset_style.name(), self.preset_seed);
+        self.add_document(document);
+    }
+
+    // The current template morphed towards the template of another tab with the Morph settings.
+    fn morphed(&self) -> DrumGen {
+        let (a, b) = (&self.doc().template, &self.documents[self.morph_target].template);
+        if self.morph_crossfade {
+            DrumGen::crossfade(a, b, self.morph_amount, self.morph_seed)
+        } else {
+            DrumGen::morph(a, b, self.morph_amount)
+        }
+    }
+
+    // Point tabs at the new paths of files that were renamed (all renames happen at once).
+    fn files_renamed(&mut self, renames: &[(PathBuf, PathBuf)]) {
+        for document in self.documents.iter_mut() {
+            let Some(picked_path) = document.picked_path.clone() else {
+                continue;
+            };
+            if let Some((_, to)) = renames.iter().find(|(from, _)| from.display().to_string() == picked_path) {
+                document.picked_path = Some(to.display().to_string());
+                if let Some(stem) = to.file_stem() {
+                    document.name = stem.to_string_lossy().to_string();
+                }
+            }
+        }
+    }
+
+    fn library_action(&mut self, action: LibraryAction) {
+        if let LibraryAction::ChooseFolder = action {
+            if let Some(dir) = rfd::FileDialog::new().pick_folder() {
+                match Library::open(&dir) {
+                    Ok(library) => self.library = Some(library),
+                    Err(err) => {
+                        log::error!("Failed to read {}: {}", dir.display(), err);
+                        self.report_error(format!("Could not read {}:\n{}", dir.display(), err));
+                    }
+                }
+            }
+            return;
+        }
+        if let Err(err) = self.library_operation(action) {
+            log::error!("Library operation failed: {}", err);
+            self.report_error(format!("Library operation failed:\n{}", err));
+        }
+    }
+
+    fn library_operation(&mut self, action: LibraryAction) -> Result<(), DrumGenError> {
+        let Some(library) = self.library.as_mut() else {
+            return Ok(());
+        };
+        match action {
+            LibraryAction::ChooseFolder => {}
+            LibraryAction::Refresh => library.refresh()?,
+            LibraryAction::Open(index) => {
+                let path = library.entry(index)?.path.clone();
+                self.open_in_tab(&path);
+            }
+            LibraryAction::Rename(index, name) => {
+                let from = library.entry(index)?.path.clone();
+                let to = library.rename(index, &name)?;
+                self.files_renamed(&[(from, to)]);
+            }
+            LibraryAction::Duplicate(index) => {
+                library.duplicate(index)?;
+            }
+            LibraryAction::Delete(index) => {
+                let path = library.entry(index)?.path.display().to_string();
+                library.delete(index)?;
+                for document in self.documents.iter_mut().filter(|document| document.picked_path.as_deref() == Some(path.as_str())) {
+                    document.picked_path = None;
+                }
+            }
+            LibraryAction::Swap(a, b) => {
+                let (path_a, path_b) = (library.entry(a)?.path.clone(), library.entry(b)?.path.clone());
+                library.swap(a, b)?;
+                self.files_renamed(&[(path_a.clone(), path_b.clone()), (path_b, path_a)]);
+            }
+        }
+        Ok(())
+    }
+
+    // Layers and steps the selection covers, or the whole part without a selection.
+    fn selected_layers(&self) -> Range<usize> {
+        self.selection.map_or(0..3, |selection| selection.layers())
+    }
+
+    fn selected_steps(&self) -> Range<usize> {
+        self.selection.map_or(0..STEP_COUNT, |selection| selection.steps())
+    }
+
+    // Steps the buttons of a layer work on: the selected ones if the selection covers the layer.
+    fn layer_steps(&self, layer: usize) -> Range<usize> {
+        match self.selection {
+            Some(selection) if selection.layers().contains(&layer) => selection.steps(),
+            _ => 0..STEP_COUNT,
+        }
+    }
+
+    // Apply an operation to the selected steps of every selected layer of the active part.
+    fn edit_selection(&mut self, f: impl Fn(&mut DrumGenSequence, Range<usize>)) {
+        let (part, layers, steps) = (self.active_part, self.selected_layers(), self.selected_steps());
+        for sequence in self.doc_mut().template.parts[part].sequence[layers].iter_mut() {
+            f(sequence, steps.clone());
+        }
+    }
+
+    // Randomize some layers of the active part with the seed from Randomize Settings,
+    // then move on to the next seed so randomizing again gives new values.
+    fn randomize(&mut self, layers: Range<usize>, steps: Range<usize>) {
+        let mut rng = StdRng::seed_from_u64(self.random_seed);
+        let (part, min, max, probability) = (self.active_part, self.random_min, self.random_max, self.random_probability);
+        for sequence in self.doc_mut().template.parts[part].sequence[layers].iter_mut() {
+            sequence.randomize_range(steps.clone(), min, max, probability, &mut rng);
+        }
+        self.last_random_seed = Some(self.random_seed);
+        self.random_seed = rng.gen();
+    }
+
+    // Shift-press on a step starts a selection, or extends the current one.
+    fn select_press(&mut self, layer: usize, step: usize) {
+        match &mut self.selection {
+            Some(selection) => selection.focus = (layer, step),
+            None => self.selection = Some(Selection { anchor: (layer, step), focus: (layer, step) }),
+        }
+    }
+
+    // Copy steps of some layers of the active part to the system clipboard, clearing them for cut.
+    fn copy_steps(&mut self, ctx: &egui::Context, layers: Range<usize>, steps: Range<usize>, cut: bool) {
+        let part = self.active_part;
+        let text = StepClip::copy(&self.doc().template.parts[part], layers.clone(), steps.clone()).to_text();
+        ctx.output_mut(|output| output.copied_text = text.clone());
+        self.copied_steps = Some(text);
+        if cut {
+            for sequence in self.doc_mut().template.parts[part].sequence[layers].iter_mut() {
+                sequence.clear_range(steps.clone());
+            }
+        }
+    }
+
+    // Paste the steps copied last in the editor into a range of steps of the active part.
+    // Without a layer the clip goes back to the layers it was copied from.
+    fn paste_steps(&mut self, first_layer: Option<usize>, steps: Range<usize>) {
+        if let Some(text) = self.copied_steps.clone() {
+            self.paste_text(&text, first_layer, steps);
+        }
+    }
+
+    fn paste_text(&mut self, text: &str, first_layer: Option<usize>, steps: Range<usize>) {
+        match StepClip::from_text(text) {
+            Ok(clip) => {
+                let part = self.active_part;
+                let first_layer = first_layer.unwrap_or(clip.first_layer);
+                clip.paste(&mut self.doc_mut().template.parts[part], first_layer, steps);
+            }
+            Err(err) => {
+                log::error!("Failed to paste from the clipboard: {}", err);
+                self.report_error(format!("The clipboard does not hold DrumGen steps:\n{}", err));
+            }
+        }
+    }
+
+    // Copy a part, or one layer of it, from another tab into the active one.
+    fn copy_from_tab(&mut self) {
+        let source = self.documents[self.copy_source].template.parts[self.copy_from_part];
+        let (to_part, layer) = (self.copy_to_part, self.copy_layer);
+        let target = &mut self.doc_mut().template.parts[to_part];
+        match layer {
+            Some(layer) => target.sequence[layer] = source.sequence[layer],
+            None => *target = source,
+        }
     }
 }
 
 impl Default for DrumgenEditor {
     fn default() -> Self {
         Self {
-            name: "DGT_0001".to_owned(),
-            template: DrumGen::new(),
+            documents: vec![Document::new("DGT_0001")],
+            active_document: 0,
             active_part: 0,
-            value_display: format!("-"),
+            value_display: "-".to_string(),
             output: vec![0; 192],
             random_min: 0,
             random_max: 10,
             random_probability: 100,
+            random_seed: 1,
+            last_random_seed: None,
             dropped_files: Vec::new(),
-            picked_path: None,
+            error_message: None,
+            show_metadata: false,
+            midi_settings: MidiExportSettings::default(),
+            show_midi_export: false,
+            learn_notes: MidiLearnSettings::default().notes.map(|notes| format_note_list(&notes)),
+            show_midi_learn: false,
+            pending_action: None,
+            after_save: None,
+            allow_close: false,
+            window_title: APP_TITLE.to_string(),
+            show_copy: false,
+            copy_source: 0,
+            copied_steps: None,
+            show_morph: false,
+            morph_target: 0,
+            morph_amount: 0.5,
+            morph_crossfade: false,
+            morph_seed: 1,
+            show_variations: false,
+            variation_count: 8,
+            variation_amount: 0.3,
+            variation_seed: 1,
+            variation_overwrite: false,
+            copy_from_part: 0,
+            copy_to_part: 0,
+            copy_layer: None,
+            show_library: false,
+            library: None,
+            library_rename: None,
+            library_delete: None,
+            name_warning: None,
+            selection: None,
+            set_value: 10,
+            scale_percent: 50,
+            tool: Tool::Paint,
+            curve_bend: 0.5,
+            repeat_every: 0,
+            euclidean_layer: None,
+            euclidean: Euclidean::default(),
+            show_preset: false,
+            preset_style: Style::FourOnTheFloor,
+            preset_variation: 0.2,
+            preset_seed: 1,
         }
     }
 }
 
 impl eframe::App for DrumgenEditor {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if ctx.input(|i| i.viewport().close_requested()) && !self.allow_close && self.documents.iter().any(Document::is_dirty) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            self.run(ctx, PendingAction::Close);
+        }
+
+        let mut undo_clicked = false;
+        let mut redo_clicked = false;
+
         egui::CentralPanel::default().show(ctx, |ui| {
 
             // Set UI selection background fill color 
@@ -101,41 +753,88 @@ impl eframe::App for DrumgenEditor {
             ui.heading(egui::RichText::new("NGEN - DrumGen Template Editor").strong());
             ui.separator();
 
+            // Tabs
+            ui.horizontal_wrapped(|ui| {
+                let mut close = None;
+                for (index, document) in self.documents.iter().enumerate() {
+                    let tab = ui.add(egui::SelectableLabel::new(index == self.active_document, document.title()));
+                    if tab.clicked() {
+                        self.active_document = index;
+                    }
+                    if let Some(path) = &document.picked_path {
+                        tab.on_hover_text(path);
+                    }
+                    if ui.small_button("✖").on_hover_text("Close tab").clicked() {
+                        close = Some(index);
+                    }
+                    ui.add_space(6.0);
+                }
+                if ui.button("+").on_hover_text("New tab").clicked() {
+                    self.new_tab();
+                }
+                if let Some(index) = close {
+                    self.request(ctx, PendingAction::CloseTab(index));
+                }
+            });
+            ui.separator();
+
             // File Management
             ui.horizontal(|ui|{
                 ui.spacing_mut().button_padding = egui::vec2(10.0, 10.0);
                 if ui.button("Open").clicked() {
-                    if let Some(path) = rfd::FileDialog::new().pick_file() {
-                        self.picked_path = Some(path.display().to_string());
-                        self.template.parse_file(path.display().to_string());
-                        if let Some(name) = path.file_stem() {
-                            self.name = format!("{}", name.to_string_lossy());
-                        }
-                        
-                    }
+                    self.request(ctx, PendingAction::Open);
                 }
                 if ui.button("Save").clicked() {
-                    match self.picked_path {
-                        Some(ref path) => {
-                            self.template.save_file(path.to_string());
-                        }
-                        None => {
-                            if let Some(path) = rfd::FileDialog::new().set_file_name(self.get_filename()).save_file() {
-                                self.template.save_file(path.display().to_string());
-                            }
-                        }
-                    }
+                    self.save();
                 }
                 if ui.button("Save As…").clicked() {
-                    if let Some(path) = rfd::FileDialog::new().set_file_name(self.get_filename()).save_file() {
-                        self.template.save_file(path.display().to_string());
+                    self.save_as();
+                }
+                ui.add_space(20.00);
+                undo_clicked = ui.add_enabled(self.doc().history.can_undo(), egui::Button::new("Undo")).on_hover_text("Ctrl+Z").clicked();
+                redo_clicked = ui.add_enabled(self.doc().history.can_redo(), egui::Button::new("Redo")).on_hover_text("Ctrl+Shift+Z").clicked();
+                ui.add_space(20.00);
+                if ui.button("Import Text…").on_hover_text("Open a template saved in the text format").clicked() {
+                    self.request(ctx, PendingAction::ImportText);
+                }
+                if ui.button("Export Text…").on_hover_text("Save the template in the text format").clicked() {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("Text template", &["txt"])
+                        .set_file_name(format!("{}.txt", self.doc().name))
+                        .save_file()
+                    {
+                        self.export_text(&path);
+                    }
+                }
+                if ui.button("Import Project…").on_hover_text("Open a template with its metadata (JSON, TOML or YAML)").clicked() {
+                    self.request(ctx, PendingAction::ImportProject);
+                }
+                if ui.button("Export Project…").on_hover_text("Save the template with its metadata (JSON, TOML or YAML)").clicked() {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("Project", &ProjectFormat::enabled_extensions())
+                        .set_file_name(format!("{}.json", self.doc().name))
+                        .save_file()
+                    {
+                        self.export_project(&path);
                     }
                 }
                 ui.add_space(20.00);
                 ui.vertical(|ui| {
                     ui.label("Template Name");
-                    ui.add_sized(egui::Vec2{x: 100.0, y: 10.0}, egui::TextEdit::singleline(&mut self.name).char_limit(8));                
+                    ui.add_sized(egui::Vec2{x: 100.0, y: 10.0}, egui::TextEdit::singleline(&mut self.doc_mut().name).char_limit(8));                
+                    if let Err(err) = validate_name(&self.doc().name) {
+                        ui.label(egui::RichText::new(format!("⚠ Saves as {}", self.doc().get_filename())).small())
+                            .on_hover_text(err.to_string());
+                    }
                 });
+                ui.toggle_value(&mut self.show_metadata, "Template Info");
+                ui.toggle_value(&mut self.show_midi_export, "Export MIDI…");
+                ui.toggle_value(&mut self.show_midi_learn, "Learn from MIDI…");
+                ui.toggle_value(&mut self.show_copy, "Copy from Tab…");
+                ui.toggle_value(&mut self.show_morph, "Morph…");
+                ui.toggle_value(&mut self.show_variations, "Variations…");
+                ui.toggle_value(&mut self.show_library, "Library…");
+                ui.toggle_value(&mut self.show_preset, "New from Preset…");
             });
 
 
@@ -158,15 +857,33 @@ impl eframe::App for DrumgenEditor {
                 if ui.add(egui::SelectableLabel::new(self.active_part == 3, "Part 4")).clicked(){
                     self.active_part = 3;
                 };
+                ui.add_space(40.0);
+                ui.label("Draw:");
+                ui.selectable_value(&mut self.tool, Tool::Paint, "Paint").on_hover_text("Drag over the steps to set them");
+                ui.selectable_value(&mut self.tool, Tool::Line, "Line").on_hover_text("Drag to draw a straight line from where the drag starts");
+                ui.selectable_value(&mut self.tool, Tool::Curve, "Curve").on_hover_text("Drag to draw a curve from where the drag starts");
+                if self.tool == Tool::Curve {
+                    ui.add(egui::Slider::new(&mut self.curve_bend, -1.0..=1.0).text("Bend"));
+                }
+                ui.add_space(20.0);
+                ui.label("Repeat every");
+                ui.add(egui::DragValue::new(&mut self.repeat_every)
+                    .clamp_range(0..=16)
+                    .custom_formatter(|n, _| if n == 0.0 { "off".to_string() } else { format!("{} steps", n) }))
+                    .on_hover_text("Copy what you draw every N steps to the end of the layer (hold 1-8 for a quick repeat)");
             });
             ui.separator();
+            add_selection_gui(ui, self);
+            add_layers_gui(ui, self);
+            ui.separator();
             ui.horizontal(|ui| {
+                let velocities = self.doc().metadata.velocities;
                 ui.vertical(|ui| {
-                    add_sequence_gui(ui, self,  0, 127);
+                    add_sequence_gui(ui, self,  0, velocities[0]);
                     ui.separator();
-                    add_sequence_gui(ui, self, 1, 100);
+                    add_sequence_gui(ui, self, 1, velocities[1]);
                     ui.separator();
-                    add_sequence_gui(ui, self, 2, 60);
+                    add_sequence_gui(ui, self, 2, velocities[2]);
                 });
                 
             });
@@ -185,12 +902,29 @@ impl eframe::App for DrumgenEditor {
                         );
                         ui.label(" | ");
                         ui.hyperlink_to(
-                            format!("NGEN User Manual"),
+                            "NGEN User Manual",
                             "https://ngen.spektroaudio.com");
                     });
                 });
             });
 
+            // Status Bar
+            egui::TopBottomPanel::bottom("status_bar")
+            .resizable(false)
+            .min_height(0.0)
+            .show_inside(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(&self.value_display);
+                    let document = self.doc();
+                    if let Some(err) = document.picked_path.as_ref().and_then(|path| validate_file_name(path).err()) {
+                        ui.colored_label(
+                            ui.visuals().warn_fg_color,
+                            format!("⚠ NGEN won't load this file: {}. Save it as {} to use it on the SD card.", err, document.get_filename()),
+                        );
+                    }
+                });
+            });
+
             // Left Panel
             egui::SidePanel::left("left_panel")
             .resizable(true)
@@ -201,7 +935,13 @@ impl eframe::App for DrumgenEditor {
                     ui.label("Shortcuts");
                 });
                 egui::ScrollArea::vertical().show(ui, |ui| {
-                    ui.label("Num 1-8: Repeat every x");
+                    ui.label("Hold 1-8 while drawing: Repeat every 1-8 steps");
+                    ui.label("Ctrl+Z: Undo");
+                    ui.label("Ctrl+Shift+Z: Redo");
+                    ui.label("Shift+Click/Drag: Select steps");
+                    ui.label("Esc: Select none");
+                    ui.label("Ctrl+C / X / V: Copy / Cut / Paste selection");
+                    ui.label("Ctrl+Click: Lock / unlock step");
                 });
             });
 
@@ -229,6 +969,17 @@ impl eframe::App for DrumgenEditor {
                                 .text("Prob").handle_shape(HandleShape::Rect { aspect_ratio: 0.5 })
                             );
                         });
+                        ui.horizontal(|ui| {
+                            ui.label("Seed");
+                            seed_edit(ui, "random_seed", &mut self.random_seed);
+                            if let Some(seed) = self.last_random_seed {
+                                let reuse = ui.small_button(format!("Last: {}", seed))
+                                    .on_hover_text("Use the last seed again: randomizing the same steps with the same settings gives the same values");
+                                if reuse.clicked() {
+                                    self.random_seed = seed;
+                                }
+                            }
+                        });
                     });
             });
 
@@ -243,7 +994,7 @@ impl eframe::App for DrumgenEditor {
                     ui.label("Output Preview");
                     egui::ScrollArea::vertical().show(ui, |ui| {
                         if ui.button("Convert").clicked() {
-                            self.output = self.template.convert();
+                            self.output = self.doc().template.convert();
                         }
                         let mut out_text: String = "".to_string();
                         for y in 0..12 {
@@ -261,6 +1012,19 @@ impl eframe::App for DrumgenEditor {
             });
 
 
+            show_metadata_window(ctx, self);
+            show_midi_export_window(ctx, self);
+            show_midi_learn_window(ctx, self);
+            show_copy_window(ctx, self);
+            show_morph_window(ctx, self);
+            show_variations_window(ctx, self);
+            show_euclidean_window(ctx, self);
+            show_preset_window(ctx, self);
+            show_library_window(ctx, self);
+            show_name_warning_dialog(ctx, self);
+            show_unsaved_changes_dialog(ctx, self);
+            show_error_dialog(ctx, &mut self.error_message);
+
             preview_files_being_dropped(ctx);
 
             // Collect dropped files:
@@ -269,13 +1033,684 @@ impl eframe::App for DrumgenEditor {
                     self.dropped_files = i.raw.dropped_files.clone();
                 }
             });
+            if !self.dropped_files.is_empty() {
+                let files = std::mem::take(&mut self.dropped_files);
+                self.open_dropped_files(ctx, files);
+            }
 
             
             
         });
+
+        // Holding the mouse button (e.g. dragging a slider) groups all changes into one undo step.
+        let in_gesture = ctx.input(|i| i.pointer.any_down());
+        let document = self.doc_mut();
+        document.history.record(&document.template, in_gesture);
+
+        // Text fields handle their own undo while focused.
+        if !ctx.wants_keyboard_input() {
+            let redo_shortcut = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::Z);
+            let redo_alt_shortcut = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Y);
+            let undo_shortcut = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z);
+            ctx.input_mut(|i| {
+                redo_clicked |= i.consume_shortcut(&redo_shortcut) || i.consume_shortcut(&redo_alt_shortcut);
+                undo_clicked |= i.consume_shortcut(&undo_shortcut);
+            });
+            if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Escape)) {
+                self.selection = None;
+            }
+            let (layers, steps) = (self.selected_layers(), self.selected_steps());
+            for event in ctx.input(|i| i.events.clone()) {
+                match event {
+                    egui::Event::Copy => self.copy_steps(ctx, layers.clone(), steps.clone(), false),
+                    egui::Event::Cut => self.copy_steps(ctx, layers.clone(), steps.clone(), true),
+                    egui::Event::Paste(text) => {
+                        // Without a selection the clip goes back to the layers it was copied from, like the Paste button.
+                        let first_layer = self.selection.map(|_| layers.start);
+                        self.paste_text(&text, first_layer, steps.clone());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if undo_clicked {
+            self.doc_mut().undo();
+        }
+        if redo_clicked {
+            self.doc_mut().redo();
+        }
+
+        let title = self.window_title();
+        if title != self.window_title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.window_title = title;
+        }
+    }
+}
+
+
+fn show_metadata_window(ctx: &egui::Context, editor: &mut DrumgenEditor) {
+    egui::Window::new("Template Info")
+        .open(&mut editor.show_metadata)
+        .resizable(false)
+        .show(ctx, |ui| {
+            let document = &mut editor.documents[editor.active_document];
+            egui::Grid::new("metadata_grid").num_columns(2).spacing([10.0, 6.0]).show(ui, |ui| {
+                ui.label("Author");
+                ui.text_edit_singleline(&mut document.metadata.author);
+                ui.end_row();
+
+                ui.label("Tags");
+                ui.text_edit_singleline(&mut document.tags_input).on_hover_text("Comma separated");
+                ui.end_row();
+
+                ui.label("Layer velocities");
+                ui.horizontal(|ui| {
+                    for velocity in document.metadata.velocities.iter_mut() {
+                        ui.add(egui::DragValue::new(velocity).clamp_range(1..=127));
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Notes");
+                ui.text_edit_multiline(&mut document.metadata.notes);
+                ui.end_row();
+            });
+            ui.label(egui::RichText::new("Template info is only kept in project files (Export Project…), not in .hex files.").small());
+        });
+}
+
+fn show_midi_export_window(ctx: &egui::Context, editor: &mut DrumgenEditor) {
+    let mut open = editor.show_midi_export;
+    let mut export = false;
+    egui::Window::new("Export MIDI")
+        .open(&mut open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            let settings = &mut editor.midi_settings;
+            egui::Grid::new("midi_export_grid").num_columns(2).spacing([10.0, 6.0]).show(ui, |ui| {
+                ui.label("Seed");
+                ui.horizontal(|ui| {
+                    seed_edit(ui, "midi_seed", &mut settings.seed);
+                });
+                ui.end_row();
+
+                ui.label("Bars");
+                ui.add(egui::DragValue::new(&mut settings.bars).clamp_range(1..=256));
+                ui.end_row();
+
+                ui.label("Tempo");
+                ui.add(egui::DragValue::new(&mut settings.tempo).clamp_range(20.0..=300.0).suffix(" BPM"));
+                ui.end_row();
+
+                for (part, note) in settings.notes.iter_mut().enumerate() {
+                    ui.label(format!("Part {} note", part + 1));
+                    ui.add(egui::DragValue::new(note).clamp_range(0..=127));
+                    ui.end_row();
+                }
+
+                ui.label("Channel");
+                let mut channel = settings.channel + 1;
+                if ui.add(egui::DragValue::new(&mut channel).clamp_range(1..=16)).changed() {
+                    settings.channel = channel - 1;
+                }
+                ui.end_row();
+            });
+            ui.label(egui::RichText::new("Each step's probability is rolled with the seed. Layers use the velocities from Template Info.").small());
+            ui.add_space(6.0);
+            if ui.button("Export…").clicked() {
+                export = true;
+            }
+        });
+    editor.show_midi_export = open;
+    if export {
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("MIDI file", &["mid"])
+            .set_file_name(format!("{}.mid", editor.doc().name))
+            .save_file()
+        {
+            editor.export_midi(&path);
+        }
+    }
+}
+
+fn format_note_list(notes: &[u8]) -> String {
+    notes.iter().map(|note| note.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+fn parse_note_list(input: &str) -> Option<Vec<u8>> {
+    input
+        .split(',')
+        .map(str::trim)
+        .filter(|note| !note.is_empty())
+        .map(|note| note.parse::<u8>().ok().filter(|note| *note <= 127))
+        .collect()
+}
+
+// Seed field with a button for a new random seed. A text field rather than a
+// DragValue, which goes through f64 and would round seeds above 2^53.
+fn seed_edit(ui: &mut Ui, id_source: &str, seed: &mut u64) {
+    let id = ui.make_persistent_id(id_source);
+    // While the field has focus it shows what is being typed, otherwise the seed itself.
+    let mut text = ui.data(|data| data.get_temp::<String>(id)).unwrap_or_else(|| seed.to_string());
+    let response = ui.add(egui::TextEdit::singleline(&mut text).id(id).desired_width(160.0));
+    if response.has_focus() {
+        if let Some(value) = parse_seed(&text) {
+            *seed = value;
+        }
+        ui.data_mut(|data| data.insert_temp(id, text));
+    } else {
+        ui.data_mut(|data| data.remove::<String>(id));
+    }
+    if ui.button("🎲").on_hover_text("New seed").clicked() {
+        *seed = rand::random();
+    }
+}
+
+fn parse_seed(input: &str) -> Option<u64> {
+    input.trim().parse().ok()
+}
+
+fn show_midi_learn_window(ctx: &egui::Context, editor: &mut DrumgenEditor) {
+    let mut open = editor.show_midi_learn;
+    let mut learn = false;
+    egui::Window::new("Learn from MIDI")
+        .open(&mut open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.label("MIDI notes mapped to each part:");
+            egui::Grid::new("midi_learn_grid").num_columns(2).spacing([10.0, 6.0]).show(ui, |ui| {
+                for (part, notes) in editor.learn_notes.iter_mut().enumerate() {
+                    ui.label(format!("Part {}", part + 1));
+                    ui.text_edit_singleline(notes);
+                    ui.end_row();
+                }
+            });
+            ui.label(egui::RichText::new("Hits are quantized to 16th notes and sorted into the layer with the closest velocity. Each step is set to how often it was hit across all loops.").small());
+            ui.add_space(6.0);
+            if ui.button("Choose MIDI files…").clicked() {
+                learn = true;
+            }
+        });
+    editor.show_midi_learn = open;
+    if learn {
+        editor.request(ctx, PendingAction::LearnMidi);
+    }
+}
+
+fn show_preset_window(ctx: &egui::Context, editor: &mut DrumgenEditor) {
+    let mut open = editor.show_preset;
+    let mut create = false;
+    egui::Window::new("New from Preset")
+        .open(&mut open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            egui::Grid::new("preset_grid").num_columns(2).spacing([10.0, 6.0]).show(ui, |ui| {
+                ui.label("Style");
+                egui::ComboBox::from_id_source("preset_style")
+                    .selected_text(editor.preset_style.name())
+                    .show_ui(ui, |ui| {
+                        for style in Style::ALL {
+                            ui.selectable_value(&mut editor.preset_style, style, style.name());
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Variation");
+                ui.add(egui::Slider::new(&mut editor.preset_variation, 0.0..=1.0));
+                ui.end_row();
+
+                ui.label("Seed");
+                ui.horizontal(|ui| {
+                    seed_edit(ui, "preset_seed", &mut editor.preset_seed);
+                });
+                ui.end_row();
+            });
+            ui.label(egui::RichText::new("Parts: kick, snare / clap, closed hats, percussion.").small());
+            ui.add_space(6.0);
+            create = ui.button("New Template").clicked();
+        });
+    editor.show_preset = open;
+    if create {
+        editor.new_from_preset();
+    }
+}
+
+fn show_euclidean_window(ctx: &egui::Context, editor: &mut DrumgenEditor) {
+    let Some(layer) = editor.euclidean_layer else {
+        return;
+    };
+    let mut open = true;
+    let mut apply = false;
+    let velocity = editor.doc().metadata.velocities[layer];
+    egui::Window::new("Euclidean Rhythm")
+        .open(&mut open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            let rhythm = &mut editor.euclidean;
+            egui::Grid::new("euclidean_grid").num_columns(2).spacing([10.0, 6.0]).show(ui, |ui| {
+                ui.label("Steps");
+                ui.add(egui::Slider::new(&mut rhythm.steps, 1..=STEP_COUNT));
+                ui.end_row();
+
+                ui.label("Pulses");
+                let steps = rhythm.steps;
+                ui.add(egui::Slider::new(&mut rhythm.pulses, 0..=steps));
+                ui.end_row();
+
+                ui.label("Rotation");
+                ui.add(egui::Slider::new(&mut rhythm.rotation, 0..=steps - 1));
+                ui.end_row();
+
+                ui.label("On value");
+                ui.add(egui::Slider::new(&mut rhythm.on_value, 0..=10));
+                ui.end_row();
+
+                ui.label("Off value");
+                ui.add(egui::Slider::new(&mut rhythm.off_value, 0..=10));
+                ui.end_row();
+            });
+            rhythm.pulses = rhythm.pulses.min(rhythm.steps);
+            rhythm.rotation = rhythm.rotation.min(rhythm.steps - 1);
+            ui.label(egui::RichText::new(rhythm.to_string()).monospace());
+            ui.label(egui::RichText::new(format!("Fills layer {} of part {}, repeating to the end.", velocity, editor.active_part + 1)).small());
+            ui.add_space(6.0);
+            apply = ui.button("Apply").clicked();
+        });
+    if apply {
+        let (steps, rhythm) = (editor.layer_steps(layer), editor.euclidean);
+        rhythm.apply_range(&mut editor.documents[editor.active_document].template.parts[editor.active_part].sequence[layer], steps);
+    }
+    if !open {
+        editor.euclidean_layer = None;
+    }
+}
+
+fn show_morph_window(ctx: &egui::Context, editor: &mut DrumgenEditor) {
+    let mut open = editor.show_morph;
+    let (mut apply, mut new_tab) = (false, false);
+    egui::Window::new("Morph")
+        .open(&mut open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            egui::Grid::new("morph_grid").num_columns(2).spacing([10.0, 6.0]).show(ui, |ui| {
+                ui.label("Towards tab");
+                egui::ComboBox::from_id_source("morph_target")
+                    .selected_text(editor.documents[editor.morph_target].title())
+                    .show_ui(ui, |ui| {
+                        for (index, document) in editor.documents.iter().enumerate() {
+                            ui.selectable_value(&mut editor.morph_target, index, document.title());
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Mode");
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut editor.morph_crossfade, false, "Blend")
+                        .on_hover_text("Interpolate every step's probability");
+                    ui.selectable_value(&mut editor.morph_crossfade, true, "Crossfade")
+                        .on_hover_text("Take each step from one template or the other");
+                });
+                ui.end_row();
+
+                ui.label("Amount");
+                ui.add(egui::Slider::new(&mut editor.morph_amount, 0.0..=1.0));
+                ui.end_row();
+
+                if editor.morph_crossfade {
+                    ui.label("Seed");
+                    ui.horizontal(|ui| {
+                        seed_edit(ui, "morph_seed", &mut editor.morph_seed);
+                    });
+                    ui.end_row();
+                }
+            });
+            ui.add_space(6.0);
+            // Live preview: A, the result and B side by side.
+            let preview = editor.morphed();
+            let size = egui::vec2(160.0, 40.0);
+            ui.horizontal(|ui| {
+                for (label, template) in [
+                    (editor.doc().title(), &editor.doc().template),
+                    ("Result".to_string(), &preview),
+                    (editor.documents[editor.morph_target].title(), &editor.documents[editor.morph_target].template),
+                ] {
+                    ui.vertical(|ui| {
+                        ui.label(egui::RichText::new(label).small());
+                        paint_density(ui, template, size);
+                    });
+                }
+            });
+            ui.add_space(6.0);
+            ui.horizontal(|ui| {
+                apply = ui.button("Apply").on_hover_text(format!("Replace {} with the result", editor.doc().title())).clicked();
+                new_tab = ui.button("New Tab").on_hover_text("Open the result in a new tab").clicked();
+            });
+        });
+    editor.show_morph = open;
+    if apply || new_tab {
+        let result = editor.morphed();
+        if apply {
+            editor.doc_mut().template = result;
+        } else {
+            let mut document = Document::new(&editor.next_name());
+            document.template = result;
+            editor.documents.push(document);
+            editor.active_document = editor.documents.len() - 1;
+        }
+    }
+}
+
+fn show_variations_window(ctx: &egui::Context, editor: &mut DrumgenEditor) {
+    let mut open = editor.show_variations;
+    let mut folder = None;
+    egui::Window::new("Variations")
+        .open(&mut open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            egui::Grid::new("variations_grid").num_columns(2).spacing([10.0, 6.0]).show(ui, |ui| {
+                ui.label("Count");
+                ui.add(egui::DragValue::new(&mut editor.variation_count).clamp_range(1..=99));
+                ui.end_row();
+
+                ui.label("Amount");
+                ui.add(egui::Slider::new(&mut editor.variation_amount, 0.0..=1.0))
+                    .on_hover_text("Chance of each hit being nudged or moved to a neighbouring step");
+                ui.end_row();
+
+                ui.label("Seed");
+                ui.horizontal(|ui| {
+                    seed_edit(ui, "variation_seed", &mut editor.variation_seed);
+                });
+                ui.end_row();
+            });
+            ui.checkbox(&mut editor.variation_overwrite, "Overwrite existing files");
+            let last = suggest_name(&format!("DGT_{:04}", editor.variation_count));
+            ui.label(egui::RichText::new(format!("Writes variations of {} as DGT_0001.HEX to {}.HEX.", editor.doc().title(), last)).small());
+            ui.add_space(6.0);
+            ui.horizontal(|ui| {
+                if let Some(library) = &editor.library {
+                    if ui.button("Generate in Library").on_hover_text(library.dir.display().to_string()).clicked() {
+                        folder = Some(library.dir.clone());
+                    }
+                }
+                if ui.button("Generate in Folder…").clicked() {
+                    folder = rfd::FileDialog::new().pick_folder();
+                }
+            });
+        });
+    editor.show_variations = open;
+    if let Some(dir) = folder {
+        editor.write_variations(&dir);
+    }
+}
+
+fn show_copy_window(ctx: &egui::Context, editor: &mut DrumgenEditor) {
+    let mut open = editor.show_copy;
+    let mut copy = false;
+    egui::Window::new("Copy from Tab")
+        .open(&mut open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            egui::Grid::new("copy_grid").num_columns(2).spacing([10.0, 6.0]).show(ui, |ui| {
+                ui.label("From tab");
+                egui::ComboBox::from_id_source("copy_source")
+                    .selected_text(editor.documents[editor.copy_source].title())
+                    .show_ui(ui, |ui| {
+                        for (index, document) in editor.documents.iter().enumerate() {
+                            ui.selectable_value(&mut editor.copy_source, index, document.title());
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("From part");
+                ui.horizontal(|ui| {
+                    for part in 0..4 {
+                        ui.selectable_value(&mut editor.copy_from_part, part, format!("{}", part + 1));
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Layer");
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut editor.copy_layer, None, "All");
+                    for (layer, velocity) in editor.doc().metadata.velocities.into_iter().enumerate() {
+                        ui.selectable_value(&mut editor.copy_layer, Some(layer), format!("{}", velocity));
+                    }
+                });
+                ui.end_row();
+
+                ui.label("To part");
+                ui.horizontal(|ui| {
+                    for part in 0..4 {
+                        ui.selectable_value(&mut editor.copy_to_part, part, format!("{}", part + 1));
+                    }
+                });
+                ui.end_row();
+            });
+            ui.label(egui::RichText::new(format!("Copies into {}.", editor.doc().title())).small());
+            ui.add_space(6.0);
+            copy = ui.button("Copy").clicked();
+        });
+    editor.show_copy = open;
+    if copy {
+        editor.copy_from_tab();
+    }
+}
+
+// Draw a 4 x 32 heatmap of how likely each part is to play on each step.
+fn paint_density(ui: &mut Ui, template: &DrumGen, size: egui::Vec2) -> Response {
+    let (rect, response) = ui.allocate_exact_size(size, Sense::click());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+    let cell = egui::vec2(rect.width() / 32.0, rect.height() / 4.0);
+    for (p, part) in template.parts.iter().enumerate() {
+        for step in 0..32 {
+            let density = part.hit_probability(step);
+            if density > 0.0 {
+                let min = rect.min + egui::vec2(step as f32 * cell.x, p as f32 * cell.y);
+                let color = Color32::from_rgba_unmultiplied(115, 193, 173, (density * 255.0) as u8);
+                painter.rect_filled(Rect::from_min_size(min, cell), 0.0, color);
+            }
+        }
+    }
+    response
+}
+
+fn show_library_window(ctx: &egui::Context, editor: &mut DrumgenEditor) {
+    let mut open = editor.show_library;
+    let mut action = None;
+    egui::Window::new("Library")
+        .open(&mut open)
+        .default_height(400.0)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Choose Folder…").on_hover_text("The DRUMGEN folder of the SD card, or a copy of it").clicked() {
+                    action = Some(LibraryAction::ChooseFolder);
+                }
+                if editor.library.is_some() && ui.button("Refresh").clicked() {
+                    action = Some(LibraryAction::Refresh);
+                }
+            });
+            let Some(library) = &editor.library else {
+                ui.label("No folder selected.");
+                return;
+            };
+            ui.label(egui::RichText::new(library.dir.display().to_string()).small());
+            ui.separator();
+            let count = library.entries.len();
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for (index, entry) in library.entries.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        match &entry.template {
+                            Ok(template) => {
+                                if paint_density(ui, template, egui::vec2(128.0, 24.0)).on_hover_text("Click to open").clicked() {
+                                    action = Some(LibraryAction::Open(index));
+                                }
+                            }
+                            Err(err) => {
+                                ui.add_sized(egui::vec2(128.0, 24.0), egui::Label::new("invalid")).on_hover_text(err);
+                            }
+                        }
+                        match &mut editor.library_rename {
+                            Some((renaming, name)) if *renaming == index => {
+                                let field = ui.add_sized(egui::vec2(80.0, 20.0), egui::TextEdit::singleline(name).char_limit(8));
+                                let entered = field.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                                if ui.button("OK").clicked() || entered {
+                                    action = Some(LibraryAction::Rename(index, name.clone()));
+                                }
+                                if ui.button("Cancel").clicked() {
+                                    action = Some(LibraryAction::Refresh);
+                                }
+                            }
+                            _ if editor.library_delete == Some(index) => {
+                                ui.label(format!("Delete {}?", entry.name));
+                                if ui.button("Delete").clicked() {
+                                    action = Some(LibraryAction::Delete(index));
+                                }
+                                if ui.button("Cancel").clicked() {
+                                    editor.library_delete = None;
+                                }
+                            }
+                            _ => {
+                                if ui.add_enabled(entry.template.is_ok(), egui::Button::new(&entry.name).min_size(egui::vec2(80.0, 0.0))).clicked() {
+                                    action = Some(LibraryAction::Open(index));
+                                }
+                                if let Some(warning) = &entry.name_warning {
+                                    ui.label("⚠").on_hover_text(format!("NGEN won't load this file: {}", warning));
+                                }
+                                if ui.add_enabled(index > 0, egui::Button::new("⏶").small()).on_hover_text("Move up").clicked() {
+                                    action = Some(LibraryAction::Swap(index, index - 1));
+                                }
+                                if ui.add_enabled(index + 1 < count, egui::Button::new("⏷").small()).on_hover_text("Move down").clicked() {
+                                    action = Some(LibraryAction::Swap(index, index + 1));
+                                }
+                                if ui.small_button("Rename").clicked() {
+                                    editor.library_rename = Some((index, suggest_name(&entry.name)));
+                                    editor.library_delete = None;
+                                }
+                                if ui.small_button("Duplicate").clicked() {
+                                    action = Some(LibraryAction::Duplicate(index));
+                                }
+                                if ui.small_button("Delete").clicked() {
+                                    editor.library_delete = Some(index);
+                                    editor.library_rename = None;
+                                }
+                            }
+                        }
+                    });
+                }
+            });
+            ui.label(egui::RichText::new("NGEN lists templates by file name, so moving a template swaps its name with its neighbour.").small());
+        });
+    editor.show_library = open;
+    if let Some(action) = action {
+        // Any action ends a rename or delete that was in progress.
+        editor.library_rename = None;
+        editor.library_delete = None;
+        editor.library_action(action);
+    }
+}
+
+fn show_name_warning_dialog(ctx: &egui::Context, editor: &mut DrumgenEditor) {
+    let Some((path, reason)) = editor.name_warning.clone() else {
+        return;
+    };
+    let path = PathBuf::from(path);
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let suggested = path.with_file_name(suggest_file_name(&stem));
+    let mut choice = None;
+    egui::Window::new("File Name")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            ui.label(format!("NGEN won't load {}:\n{}", path.display(), reason));
+            if suggested.exists() {
+                ui.label(format!("{} already exists and will be replaced.", suggested.display()));
+            }
+            ui.add_space(10.0);
+            ui.horizontal(|ui| {
+                let file_name = suggested.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+                if ui.button(format!("Save as {}", file_name)).clicked() {
+                    choice = Some(Some(suggested.clone()));
+                }
+                if ui.button("Save Anyway").clicked() {
+                    choice = Some(Some(path.clone()));
+                }
+                if ui.button("Cancel").clicked() {
+                    choice = Some(None);
+                }
+            });
+        });
+    if let Some(choice) = choice {
+        editor.name_warning = None;
+        let saved = choice.is_some_and(|path| editor.write_file(path.display().to_string()));
+        if let Some(action) = editor.after_save.take().filter(|_| saved) {
+            editor.run(ctx, action);
+        }
+    }
+}
+
+fn show_unsaved_changes_dialog(ctx: &egui::Context, editor: &mut DrumgenEditor) {
+    if editor.pending_action.is_none() {
+        return;
+    }
+    let mut save = false;
+    let mut discard = false;
+    let mut cancel = false;
+    egui::Window::new("Unsaved Changes")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            ui.label(format!("{} has unsaved changes. Save them first?", editor.doc().get_filename()));
+            ui.add_space(10.0);
+            ui.horizontal(|ui| {
+                save = ui.button("Save").clicked();
+                discard = ui.button("Discard").clicked();
+                cancel = ui.button("Cancel").clicked();
+            });
+        });
+    let saved = save && editor.save();
+    if save && !saved && editor.name_warning.is_some() {
+        // The file name dialog finishes the save and then carries on with the action.
+        editor.after_save = editor.pending_action.take();
+    } else if cancel || save && !saved {
+        editor.pending_action = None;
+    } else if saved || discard {
+        if let Some(action) = editor.pending_action.take() {
+            // When quitting, a discarded tab is closed so the next tab with changes is asked about.
+            if discard && matches!(action, PendingAction::Close) {
+                editor.close_tab(editor.active_document);
+            }
+            editor.run(ctx, action);
+        }
     }
 }
 
+fn show_error_dialog(ctx: &egui::Context, error_message: &mut Option<String>) {
+    let mut dismissed = false;
+    if let Some(message) = error_message {
+        egui::Window::new("Error")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+            .show(ctx, |ui| {
+                // Several errors can be shown at once, e.g. for a batch of dropped files.
+                egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    ui.label(message.as_str());
+                });
+                ui.add_space(10.0);
+                if ui.button("OK").clicked() {
+                    dismissed = true;
+                }
+            });
+    }
+    if dismissed {
+        *error_message = None;
+    }
+}
 
 fn preview_files_being_dropped(ctx: &egui::Context) {
     use egui::*;
@@ -313,10 +1748,139 @@ fn preview_files_being_dropped(ctx: &egui::Context) {
 
 
 
+// How the layers of the active part play together, with a warning where they overlap.
+fn add_layers_gui(ui: &mut Ui, editor: &mut DrumgenEditor) {
+    let part = editor.doc().template.parts[editor.active_part];
+    let velocities = editor.doc().metadata.velocities;
+    ui.horizontal(|ui| {
+        ui.label("Plays:");
+        paint_outcomes(ui, &part, velocities);
+        let overlaps = part.overlaps();
+        if overlaps.is_empty() {
+            return;
+        }
+        let shadowed = overlaps.iter().filter(|overlap| !overlap.shadowed.is_empty()).count();
+        let mut warning = format!("⚠ Layers overlap on {} steps", overlaps.len());
+        if shadowed > 0 {
+            warning.push_str(&format!(", {} with a layer that never plays", shadowed));
+        }
+        let details: Vec<String> = overlaps.iter()
+            .map(|overlap| {
+                let outcome = part.outcome(overlap.step);
+                let layers: Vec<String> = overlap.layers.iter()
+                    .map(|layer| format!("{} {:.0}% (set to {}%)", velocities[*layer], outcome.layers[*layer] * 100.0, part.sequence[*layer].steps[overlap.step] as u32 * 10))
+                    .collect();
+                format!("Step {}: {}", overlap.step + 1, layers.join(", "))
+            })
+            .collect();
+        ui.label(egui::RichText::new(warning).color(ui.visuals().warn_fg_color))
+            .on_hover_text(format!("Louder layers are rolled first, so quieter ones only play when they miss.\n\n{}", details.join("\n")));
+        let normalize = ui.button("Normalize Layers")
+            .on_hover_text("Keep only the layer most likely to play on each overlapping step, set to the step's overall chance (steps with a lock are left alone)");
+        if normalize.clicked() {
+            let active_part = editor.active_part;
+            editor.doc_mut().template.parts[active_part].normalize_layers();
+        }
+    });
+}
+
+// Draw how likely each layer of a part is to play on each step, stacked from the loudest layer at the bottom.
+fn paint_outcomes(ui: &mut Ui, part: &DrumGenLayer, velocities: [u8; 3]) -> Response {
+    let (rect, response) = ui.allocate_exact_size(egui::vec2(320.0, 24.0), Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+    let width = rect.width() / STEP_COUNT as f32;
+    let outcomes = part.outcomes();
+    for (step, outcome) in outcomes.iter().enumerate() {
+        let mut bottom = rect.bottom();
+        for (layer, chance) in outcome.layers.iter().enumerate() {
+            let height = chance * rect.height();
+            let min = egui::pos2(rect.left() + step as f32 * width, bottom - height);
+            let color = Color32::from_rgba_unmultiplied(115, 193, 173, [255, 160, 80][layer]);
+            painter.rect_filled(Rect::from_min_size(min, egui::vec2(width - 1.0, height)), 0.0, color);
+            bottom -= height;
+        }
+    }
+    match response.hover_pos() {
+        Some(pos) => {
+            let step = (((pos.x - rect.left()) / width) as usize).min(STEP_COUNT - 1);
+            let chances: Vec<String> = outcomes[step].layers.iter().zip(velocities)
+                .map(|(chance, velocity)| format!("{}: {:.0}%", velocity, chance * 100.0))
+                .collect();
+            response.on_hover_text(format!("Step {}: {}, silent {:.0}%", step + 1, chances.join(", "), outcomes[step].silence() * 100.0))
+        }
+        None => response,
+    }
+}
+
+// Operations on the selected steps of the active part (the whole part without a selection).
+fn add_selection_gui(ui: &mut Ui, editor: &mut DrumgenEditor) {
+    ui.horizontal(|ui| {
+        let velocities = editor.doc().metadata.velocities;
+        match editor.selection {
+            Some(selection) => {
+                let (layers, steps) = (selection.layers(), selection.steps());
+                ui.label(format!(
+                    "Selection: velocity {}–{}, steps {}–{}",
+                    velocities[layers.start], velocities[layers.end - 1], steps.start + 1, steps.end
+                ));
+                if ui.small_button("Select None").clicked() {
+                    editor.selection = None;
+                }
+            }
+            None => {
+                ui.label("Selection: whole part").on_hover_text("Hold Shift and click or drag over steps to select them");
+            }
+        }
+        ui.add_space(20.0);
+        if ui.button("Set").clicked() {
+            let value = editor.set_value;
+            editor.edit_selection(|sequence, steps| sequence.set_range(steps, value));
+        }
+        ui.add(egui::DragValue::new(&mut editor.set_value).clamp_range(0..=10));
+        if ui.button("Scale").clicked() {
+            let factor = editor.scale_percent as f32 / 100.0;
+            editor.edit_selection(|sequence, steps| sequence.scale_range(steps, factor));
+        }
+        ui.add(egui::DragValue::new(&mut editor.scale_percent).clamp_range(0..=400).suffix("%"));
+        ui.add_space(10.0);
+        if ui.button("Randomize").clicked() {
+            editor.randomize(editor.selected_layers(), editor.selected_steps());
+        }
+        if ui.button("Clear").clicked() {
+            editor.edit_selection(|sequence, steps| sequence.clear_range(steps));
+        }
+        if ui.button("<<").on_hover_text("Shift left within the selection").clicked() {
+            editor.edit_selection(|sequence, steps| sequence.shift_range(steps, 1));
+        }
+        if ui.button(">>").on_hover_text("Shift right within the selection").clicked() {
+            editor.edit_selection(|sequence, steps| sequence.shift_range(steps, -1));
+        }
+        let repeat = ui.add_enabled(editor.selection.is_some(), egui::Button::new("Repeat"))
+            .on_hover_text("Repeat the selected steps to the end of their layers");
+        if repeat.clicked() {
+            editor.edit_selection(|sequence, steps| sequence.repeat(steps.start, steps.len(), STEP_COUNT));
+        }
+        ui.add_space(10.0);
+        let (layers, steps) = (editor.selected_layers(), editor.selected_steps());
+        if ui.button("Copy").clicked() {
+            editor.copy_steps(ui.ctx(), layers.clone(), steps.clone(), false);
+        }
+        if ui.button("Cut").clicked() {
+            editor.copy_steps(ui.ctx(), layers.clone(), steps.clone(), true);
+        }
+        let paste = ui.add_enabled(editor.copied_steps.is_some(), egui::Button::new("Paste"))
+            .on_hover_text("Pasting repeats shorter clips to fill the selection. Ctrl+V also pastes steps copied in another window");
+        if paste.clicked() {
+            let first_layer = editor.selection.map(|_| layers.start);
+            editor.paste_steps(first_layer, steps);
+        }
+    });
+}
+
 pub fn add_sequence_gui(ui: &mut Ui, editor: &mut DrumgenEditor, layer: usize, velocity: u8, ) {
     ui.label(egui::RichText::new(format!("VELOCITY: {}", velocity)).strong());
     ui.horizontal(|ui| {
-        ui.spacing_mut().slider_width = 50.0;
 
         egui::ScrollArea::horizontal()
             .scroll_bar_visibility(egui::containers::scroll_area::ScrollBarVisibility::VisibleWhenNeeded)
@@ -326,94 +1890,132 @@ pub fn add_sequence_gui(ui: &mut Ui, editor: &mut DrumgenEditor, layer: usize, v
                 
                 
                 let button_size = egui::vec2(36.0, 20.0);
+                // The buttons work on the selected steps when the selection covers this layer.
+                let steps = editor.layer_steps(layer);
                 ui.horizontal(|ui| {
-                    let randomize_button = ui.add_sized(button_size.clone(), egui::Button::new("R")).on_hover_text("Randomize");
-                    let clear_button = ui.add_sized(button_size.clone(), egui::Button::new("C")).on_hover_text("Clear");
+                    let randomize_button = ui.add_sized(button_size, egui::Button::new("R")).on_hover_text("Randomize");
+                    let clear_button = ui.add_sized(button_size, egui::Button::new("C")).on_hover_text("Clear");
 
                     if randomize_button.clicked() {
-                        editor.template.parts[editor.active_part].sequence[layer].randomize(editor.random_min, editor.random_max, editor.random_probability);
+                        editor.randomize(layer..layer + 1, steps.clone());
                     }
 
                     if clear_button.clicked() {
-                        editor.template.parts[editor.active_part].sequence[layer].clear();
+                        editor.documents[editor.active_document].template.parts[editor.active_part].sequence[layer].clear_range(steps.clone());
                     }
                 });
                 ui.horizontal(|ui| {
-                    let shift_left = ui.add_sized(button_size.clone(), egui::Button::new("<<")).on_hover_text("Shift left");
-                    let shift_right = ui.add_sized(button_size.clone(), egui::Button::new(">>")).on_hover_text("Shift right");
+                    let euclidean = ui.add_sized(egui::vec2(button_size.x * 2.0 + ui.spacing().item_spacing.x, button_size.y), egui::SelectableLabel::new(editor.euclidean_layer == Some(layer), "Euclid…")).on_hover_text("Fill with a Euclidean rhythm");
+                    if euclidean.clicked() {
+                        editor.euclidean_layer = if editor.euclidean_layer == Some(layer) { None } else { Some(layer) };
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let shift_left = ui.add_sized(button_size, egui::Button::new("<<")).on_hover_text("Shift left");
+                    let shift_right = ui.add_sized(button_size, egui::Button::new(">>")).on_hover_text("Shift right");
                     if shift_left.clicked() {
-                        editor.template.parts[editor.active_part].sequence[layer].shift(1);
+                        editor.documents[editor.active_document].template.parts[editor.active_part].sequence[layer].shift_range(steps.clone(), 1);
                     }
                     if shift_right.clicked() {
-                        editor.template.parts[editor.active_part].sequence[layer].shift(-1);
+                        editor.documents[editor.active_document].template.parts[editor.active_part].sequence[layer].shift_range(steps.clone(), -1);
                     }
                 });
                 ui.horizontal(|ui| {
-                    let minus_one = ui.add_sized(button_size.clone(), egui::Button::new("-1")).on_hover_text("Add -1");
-                    let plus_one = ui.add_sized(button_size.clone(), egui::Button::new("+1")).on_hover_text("Add +1");
+                    let copy = ui.add_sized(button_size, egui::Button::new("Copy")).on_hover_text("Copy the selected steps of this layer");
+                    let cut = ui.add_sized(button_size, egui::Button::new("Cut")).on_hover_text("Cut the selected steps of this layer");
+                    if copy.clicked() {
+                        editor.copy_steps(ui.ctx(), layer..layer + 1, steps.clone(), false);
+                    }
+                    if cut.clicked() {
+                        editor.copy_steps(ui.ctx(), layer..layer + 1, steps.clone(), true);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let paste = ui.add_enabled_ui(editor.copied_steps.is_some(), |ui| {
+                        ui.add_sized(button_size, egui::Button::new("Paste")).on_hover_text("Paste into the selected steps of this layer")
+                    }).inner;
+                    if paste.clicked() {
+                        editor.paste_steps(Some(layer), steps.clone());
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let minus_one = ui.add_sized(button_size, egui::Button::new("-1")).on_hover_text("Add -1");
+                    let plus_one = ui.add_sized(button_size, egui::Button::new("+1")).on_hover_text("Add +1");
                     if minus_one.clicked() {
-                        editor.template.parts[editor.active_part].sequence[layer].add(-1);
+                        editor.documents[editor.active_document].template.parts[editor.active_part].sequence[layer].add_range(steps.clone(), -1);
                     }
                     if plus_one.clicked() {
-                        editor.template.parts[editor.active_part].sequence[layer].add(1);
+                        editor.documents[editor.active_document].template.parts[editor.active_part].sequence[layer].add_range(steps.clone(), 1);
                     }
                 });
             });
             ui.add_space(20.0);
-                let seq_len = editor.template.parts[editor.active_part].sequence[layer].steps.len();
-                for i in 0..seq_len {
-                    let slider = ui.add(egui::Slider::new(&mut editor.template.parts[editor.active_part].sequence[layer].steps[i], 0..=10)
-                    .vertical()
-                    .show_value(false).text(format!(" {}", i+1))
-                    .handle_shape(HandleShape::Rect { aspect_ratio: 0.5 })
-                    .trailing_fill(true)
-                    
-                
-                    );
-                    if slider.hovered() {
-                        editor.value_display = format!("Probability: {}%", editor.template.parts[editor.active_part].sequence[layer].steps[i] as f32 * 10.0);
-                    };
-                    if slider.drag_released() {
-                        // Check if holding down the number 1
-                        let mut skip_value = 0;
-                        if ui.input(|ui| ui.key_down(egui::Key::Num1)) {
-                            skip_value = 1;
-                        }
-                        if ui.input(|ui| ui.key_down(egui::Key::Num2)) {
-                            skip_value = 2;
-                        }
-                        if ui.input(|ui| ui.key_down(egui::Key::Num3)) {
-                            skip_value = 3;
-                        }
-                        if ui.input(|ui| ui.key_down(egui::Key::Num4)) {
-                            skip_value = 4;
-                        }
-                        if ui.input(|ui| ui.key_down(egui::Key::Num5)) {
-                            skip_value = 5;
-                        }
-                        if ui.input(|ui| ui.key_down(egui::Key::Num6)) {
-                            skip_value = 6;
-                        }
-                        if ui.input(|ui| ui.key_down(egui::Key::Num7)) {
-                            skip_value = 7;
-                        }
-                        if ui.input(|ui| ui.key_down(egui::Key::Num8)) {
-                            skip_value = 8;
-                        }
-                        if skip_value > 0 {
-                            let mut index = i;
-                            for _ in i..editor.template.parts[editor.active_part].sequence[layer].steps.len() {
-                                index += skip_value;
-                                if index < editor.template.parts[editor.active_part].sequence[layer].steps.len() {
-                                    editor.template.parts[editor.active_part].sequence[layer].steps[index] = editor.template.parts[editor.active_part].sequence[layer].steps[i];
-                                }
-                            }
+                // While Shift is held the grid selects steps instead of changing them,
+                // and while Ctrl (Cmd on macOS) is held clicks lock or unlock steps.
+                let (selecting, locking, pressed, down, pointer) = ui.input(|i| {
+                    (i.modifiers.shift, i.modifiers.command, i.pointer.primary_pressed(), i.pointer.primary_down(), i.pointer.interact_pos())
+                });
+                let highlight = editor.selection.filter(|selection| selection.layers().contains(&layer)).map(|selection| selection.steps());
+                let (tool, bend, repeat_every) = (editor.tool, editor.curve_bend, editor.repeat_every);
+                let sequence = &mut editor.documents[editor.active_document].template.parts[editor.active_part].sequence[layer];
+                let locked = sequence.locked();
+                let grid = ui.add(StepGrid::new(("step_grid", layer), &mut sequence.steps)
+                    .tool(tool, bend)
+                    .editable(!selecting && !locking)
+                    .highlight(highlight)
+                    .locked(locked)
+                );
+                if let Some(pointer) = pointer.filter(|pointer| locking && !selecting && pressed && grid.rect.contains(*pointer)) {
+                    let mut locked = locked;
+                    locked.toggle(step_grid::step_at(grid.rect, pointer));
+                    sequence.set_locked(locked);
+                }
+                // Repeat the period being drawn in to the end of the layer, live while dragging.
+                // Holding a number key 1-8 repeats every that many steps instead of the setting.
+                let period = held_number(ui).unwrap_or(repeat_every);
+                if let Some(pointer) = grid.interact_pointer_pos().filter(|_| period > 0 && grid.changed()) {
+                    let from = step_grid::step_at(grid.rect, pointer) / period * period;
+                    sequence.repeat(from, period, STEP_COUNT);
+                }
+                if let Some(pointer) = pointer.filter(|pointer| selecting && grid.rect.contains(*pointer)) {
+                    let i = step_grid::step_at(grid.rect, pointer);
+                    if pressed {
+                        editor.select_press(layer, i);
+                    } else if down {
+                        if let Some(selection) = &mut editor.selection {
+                            selection.focus = (layer, i);
                         }
-                    };
-                    if i % 4 == 3 && i < (seq_len - 1) {
-                        ui.add_space(20.0f32)
                     }
                 }
+                if let Some(hover) = grid.hover_pos() {
+                    let i = step_grid::step_at(grid.rect, hover);
+                    let sequence = &editor.documents[editor.active_document].template.parts[editor.active_part].sequence[layer];
+                    let lock = if sequence.locked().contains(i) { " (locked)" } else { "" };
+                    editor.value_display = format!("Step {}: probability {}%{}", i + 1, sequence.steps[i] as f32 * 10.0, lock);
+                }
             });
     });
-}
\ No newline at end of file
+}
+
+// Number key 1-8 held down, if any.
+fn held_number(ui: &Ui) -> Option<usize> {
+    let keys = [Key::Num1, Key::Num2, Key::Num3, Key::Num4, Key::Num5, Key::Num6, Key::Num7, Key::Num8];
+    ui.input(|i| keys.iter().position(|key| i.key_down(*key)).map(|index| index + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeds_survive_being_shown_and_typed_back() {
+        let mut rng = StdRng::seed_from_u64(0x5EED);
+        let seeds = (0..100).map(|_| rng.gen::<u64>()).chain([0, 1 << 53, (1 << 53) + 1, u64::MAX]);
+        for seed in seeds {
+            assert_eq!(parse_seed(&seed.to_string()), Some(seed));
+        }
+        assert_eq!(parse_seed(" 42 "), Some(42));
+        assert_eq!(parse_seed("-1"), None);
+        assert_eq!(parse_seed(""), None);
+    }
+}
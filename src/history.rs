@@ -0,0 +1,200 @@
+/*
+    Undo / redo history based on snapshots.
+
+    The editor hands the template to `record` once per frame and the history
+    compares it with the last state it saw. Changes made while a gesture is in
+    progress (e.g. the mouse button is held down on a slider) are grouped into
+    a single undo step that is committed when the gesture ends.
+*/
+use std::collections::VecDeque;
+
+/// Default number of undo steps kept.
+pub const DEFAULT_DEPTH: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct History<T> {
+    undo_stack: VecDeque<T>,
+    redo_stack: Vec<T>,
+    // Last state passed to `record`, or None until the first call after `clear`.
+    last: Option<T>,
+    // State at the start of the gesture currently being recorded.
+    pending: Option<T>,
+    max_depth: usize,
+}
+
+impl<T: Clone + PartialEq> History<T> {
+    pub fn new(max_depth: usize) -> History<T> {
+        History {
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            last: None,
+            pending: None,
+            max_depth: max_depth.max(1),
+        }
+    }
+
+    /// Record the current state.
+    /// Pass `in_gesture = true` while the change should keep growing the same undo step.
+    pub fn record(&mut self, state: &T, in_gesture: bool) {
+        let last = match self.last.replace(state.clone()) {
+            Some(last) => last,
+            None => return,
+        };
+        if self.pending.is_none() && last != *state {
+            self.pending = Some(last);
+        }
+        if in_gesture {
+            return;
+        }
+        if let Some(start) = self.pending.take() {
+            if start != *state {
+                self.push(start);
+            }
+        }
+    }
+
+    /// Push a state onto the undo stack, e.g. before a change made outside of `record`.
+    pub fn push(&mut self, state: T) {
+        self.undo_stack.push_back(state);
+        while self.undo_stack.len() > self.max_depth {
+            self.undo_stack.pop_front();
+        }
+        self.redo_stack.clear();
+    }
+
+    /// Return the previous state, or None if there is nothing to undo.
+    pub fn undo(&mut self, current: &T) -> Option<T> {
+        if self.pending.is_some() {
+            return None;
+        }
+        let state = self.undo_stack.pop_back()?;
+        self.redo_stack.push(current.clone());
+        self.last = Some(state.clone());
+        Some(state)
+    }
+
+    /// Return the state that was undone last, or None if there is nothing to redo.
+    pub fn redo(&mut self, current: &T) -> Option<T> {
+        if self.pending.is_some() {
+            return None;
+        }
+        let state = self.redo_stack.pop()?;
+        self.undo_stack.push_back(current.clone());
+        self.last = Some(state.clone());
+        Some(state)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty() && self.pending.is_none()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty() && self.pending.is_none()
+    }
+
+    /// Forget all steps. The next recorded state becomes the new starting point.
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.last = None;
+        self.pending = None;
+    }
+}
+
+impl<T: Clone + PartialEq> Default for History<T> {
+    fn default() -> Self {
+        History::new(DEFAULT_DEPTH)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Start a history at `state`.
+    fn history_at(max_depth: usize, state: i32) -> History<i32> {
+        let mut history = History::new(max_depth);
+        history.record(&state, false);
+        history
+    }
+
+    #[test]
+    fn undo_and_redo_single_changes() {
+        let mut history = history_at(10, 0);
+        history.record(&1, false);
+        history.record(&2, false);
+        history.record(&2, false);
+
+        assert_eq!(history.undo(&2), Some(1));
+        assert_eq!(history.undo(&1), Some(0));
+        assert_eq!(history.undo(&0), None);
+        assert_eq!(history.redo(&0), Some(1));
+        assert_eq!(history.redo(&1), Some(2));
+        assert_eq!(history.redo(&2), None);
+    }
+
+    #[test]
+    fn undone_state_is_not_recorded_as_a_change() {
+        let mut history = history_at(10, 0);
+        history.record(&1, false);
+        assert_eq!(history.undo(&1), Some(0));
+        history.record(&0, false);
+        assert!(history.can_redo());
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn gesture_is_a_single_step() {
+        let mut history = history_at(10, 0);
+        history.record(&1, true);
+        history.record(&2, true);
+        assert!(!history.can_undo());
+        assert_eq!(history.undo(&2), None);
+        history.record(&3, true);
+        history.record(&3, false);
+
+        assert_eq!(history.undo(&3), Some(0));
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn gesture_that_ends_unchanged_is_dropped() {
+        let mut history = history_at(10, 0);
+        history.record(&1, true);
+        history.record(&0, false);
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn new_change_clears_redo() {
+        let mut history = history_at(10, 0);
+        history.record(&1, false);
+        assert_eq!(history.undo(&1), Some(0));
+        history.record(&5, false);
+        assert!(!history.can_redo());
+        assert_eq!(history.undo(&5), Some(0));
+    }
+
+    #[test]
+    fn clear_starts_over_from_the_next_state() {
+        let mut history = history_at(10, 0);
+        history.record(&1, false);
+        history.clear();
+        history.record(&7, false);
+        assert!(!history.can_undo());
+        history.record(&8, false);
+        assert_eq!(history.undo(&8), Some(7));
+    }
+
+    #[test]
+    fn depth_is_bounded() {
+        let mut history = history_at(3, 0);
+        for i in 1..=10 {
+            history.record(&i, false);
+        }
+        assert_eq!(history.undo(&10), Some(9));
+        assert_eq!(history.undo(&9), Some(8));
+        assert_eq!(history.undo(&8), Some(7));
+        assert_eq!(history.undo(&7), None);
+    }
+}
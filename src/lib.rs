@@ -0,0 +1,43 @@
+//! Reading, editing and writing templates for the
+//! [DrumGen generator](https://ngen.spektroaudio.com/generators/#drumgen) of NGEN.
+//!
+//! A [`DrumGen`] template holds 4 parts ([`DrumGenLayer`]), each with 3 velocity
+//! layers ([`DrumGenSequence`]) of 32 probability steps (0 - 10).
+//!
+//! ```no_run
+//! use drumgen::DrumGen;
+//!
+//! let mut template = DrumGen::new();
+//! template.parse_file("DRUM0001.HEX")?;
+//! template.parts[0].sequence[0].set_step(0, 10);
+//! template.save_file("DRUM0002.HEX")?;
+//! # Ok::<(), drumgen::DrumGenError>(())
+//! ```
+//!
+//! The editor GUI is only built with the `gui` feature (enabled by default).
+//! Depend on this crate with `default-features = false` to use the model alone.
+//!
+//! Optional features:
+//!
+//! - `serde`: `Serialize` / `Deserialize` for the template types.
+//! - `json`, `toml`, `yaml`: read and write [`DrumGenProject`] files, which carry
+//!   metadata (name, author, tags, layer velocities, notes) the .hex format can't hold.
+//! - `midi`: render a template into a drum pattern and save it as a Standard MIDI File.
+
+pub mod drumgen;
+pub mod filename;
+pub mod generate;
+pub mod library;
+#[cfg(feature = "midi")]
+pub mod midi;
+pub mod project;
+pub mod text;
+
+pub use crate::drumgen::{
+    DrumGen, DrumGenError, DrumGenLayer, DrumGenSequence, LayerOverlap, StepMask, StepOutcome,
+    LAYER_SIZE, LAYER_VELOCITIES, MAX_STEP_VALUE, SEQUENCE_SIZE, STEP_COUNT, TEMPLATE_SIZE,
+};
+pub use crate::generate::{Euclidean, Style};
+pub use crate::library::{Library, LibraryEntry};
+pub use crate::project::{DrumGenProject, ProjectFormat, TemplateMetadata};
+pub use crate::text::StepClip;
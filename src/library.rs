@@ -0,0 +1,306 @@
+/*
+    Template library.
+
+    A Library lists the .hex templates in a folder, usually the DRUMGEN
+    folder of a mounted NGEN SD card or a local copy of it, and keeps the
+    files in sync when templates are renamed, duplicated, deleted or moved.
+
+    NGEN lists templates in file name order, so moving a template up or down
+    swaps its file name with its neighbour.
+*/
+use crate::drumgen::{DrumGen, DrumGenError};
+use crate::filename::{suggest_file_name, suggest_name, validate_file_name, validate_name, MAX_NAME_LENGTH};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use log::{error, info};
+
+/// A .hex file in the library.
+#[derive(Debug)]
+pub struct LibraryEntry {
+    pub path: PathBuf,
+    /// File name without the extension.
+    pub name: String,
+    /// Why NGEN would not load this file because of its name, if it wouldn't.
+    pub name_warning: Option<String>,
+    /// The decoded template, or the reason it could not be read.
+    pub template: Result<DrumGen, String>,
+}
+
+/// The .hex templates of one folder, sorted by file name.
+#[derive(Debug)]
+pub struct Library {
+    pub dir: PathBuf,
+    pub entries: Vec<LibraryEntry>,
+}
+
+impl Library {
+    /// List the templates in a folder.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Library, DrumGenError> {
+        let mut library = Library { dir: dir.as_ref().to_path_buf(), entries: Vec::new() };
+        library.refresh()?;
+        Ok(library)
+    }
+
+    /// Read the folder again.
+    pub fn refresh(&mut self) -> Result<(), DrumGenError> {
+        info!("Reading library: {}", self.dir.display());
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let is_hex = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("hex"));
+            if !is_hex || !path.is_file() {
+                continue;
+            }
+            let name = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+            let mut template = DrumGen::new();
+            let template = template.parse_file(&path).map(|()| template).map_err(|err| err.to_string());
+            let name_warning = validate_file_name(&path).err().map(|err| err.to_string());
+            entries.push(LibraryEntry { path, name, name_warning, template });
+        }
+        entries.sort_by_key(|entry| entry.name.to_uppercase());
+        self.entries = entries;
+        Ok(())
+    }
+
+    /// Path of the template with the given name in this folder.
+    pub fn path_for(&self, name: &str) -> PathBuf {
+        self.dir.join(suggest_file_name(name))
+    }
+
+    /// The template at `index`, or an error if the index is out of date.
+    pub fn entry(&self, index: usize) -> Result<&LibraryEntry, DrumGenError> {
+        self.entries.get(index).ok_or_else(|| {
+            DrumGenError::Io(io::Error::new(io::ErrorKind::NotFound, format!("no template at position {} of the library", index + 1)))
+        })
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.entries.iter().any(|entry| entry.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Rename a template. The name is uppercased and must follow the NGEN file name rules.
+    /// Returns the new path.
+    pub fn rename(&mut self, index: usize, name: &str) -> Result<PathBuf, DrumGenError> {
+        let name = name.trim().to_uppercase();
+        validate_name(&name)?;
+        let entry = self.entry(index)?;
+        let from = entry.path.clone();
+        let to = self.path_for(&name);
+        if !entry.name.eq_ignore_ascii_case(&name) && self.contains(&name) {
+            return Err(already_exists(&to));
+        }
+        info!("Renaming {} to {}", from.display(), to.display());
+        fs::rename(&from, &to)?;
+        self.refresh()?;
+        Ok(to)
+    }
+
+    /// Copy a template under the next free name (DGT_0001 becomes DGT_0002).
+    /// Returns the index of the copy.
+    pub fn duplicate(&mut self, index: usize) -> Result<usize, DrumGenError> {
+        let from = self.entry(index)?.path.clone();
+        let name = self.free_name(&self.entries[index].name)?;
+        let to = self.path_for(&name);
+        info!("Copying {} to {}", from.display(), to.display());
+        fs::copy(&from, &to)?;
+        self.refresh()?;
+        Ok(self.entries.iter().position(|entry| entry.path == to).unwrap_or(index))
+    }
+
+    /// Delete a template file.
+    pub fn delete(&mut self, index: usize) -> Result<(), DrumGenError> {
+        let path = &self.entry(index)?.path;
+        info!("Deleting {}", path.display());
+        fs::remove_file(path)?;
+        self.entries.remove(index);
+        Ok(())
+    }
+
+    /// Swap the file names of two templates, which swaps their places in NGEN's list.
+    pub fn swap(&mut self, a: usize, b: usize) -> Result<(), DrumGenError> {
+        if a == b {
+            return Ok(());
+        }
+        let (path_a, path_b) = (self.entry(a)?.path.clone(), self.entry(b)?.path.clone());
+        // Never reuse a file that is already there, e.g. one left behind by a crash.
+        let temporary = (0..)
+            .map(|n| self.dir.join(format!(".drumgen_swap_{}.tmp", n)))
+            .find(|path| !path.exists())
+            .expect("a free temporary file name");
+        info!("Swapping {} and {}", path_a.display(), path_b.display());
+        fs::rename(&path_a, &temporary)?;
+        if let Err(err) = fs::rename(&path_b, &path_a) {
+            undo_renames(&[(&temporary, &path_a)]);
+            return Err(err.into());
+        }
+        if let Err(err) = fs::rename(&temporary, &path_b) {
+            undo_renames(&[(&path_a, &path_b), (&temporary, &path_a)]);
+            return Err(err.into());
+        }
+        self.refresh()
+    }
+
+    // First valid name based on `name` that no template in the folder uses.
+    fn free_name(&self, name: &str) -> Result<String, DrumGenError> {
+        let name = suggest_name(name);
+        let base = name.trim_end_matches(|c: char| c.is_ascii_digit());
+        let digits = &name[base.len()..];
+        let (start, width) = match digits.parse::<u32>() {
+            Ok(number) => (number + 1, digits.len()),
+            Err(_) => (1, 1),
+        };
+        (start..)
+            .map(|number| format!("{:0width$}", number, width = width))
+            .take_while(|number| number.len() <= MAX_NAME_LENGTH)
+            .map(|number| {
+                let base: String = base.chars().take(MAX_NAME_LENGTH - number.len()).collect();
+                format!("{}{}", base, number)
+            })
+            .find(|candidate| validate_name(candidate).is_ok() && !self.contains(candidate))
+            .ok_or_else(|| DrumGenError::InvalidName(format!("no free name left after `{}`", name)))
+    }
+}
+
+// Move files back after a failed swap, so no template is left under a temporary name.
+fn undo_renames(renames: &[(&PathBuf, &PathBuf)]) {
+    for (from, to) in renames {
+        if let Err(err) = fs::rename(from, to) {
+            error!("Could not move {} back to {}: {}", from.display(), to.display(), err);
+        }
+    }
+}
+
+fn already_exists(path: &Path) -> DrumGenError {
+    DrumGenError::Io(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", path.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Create an empty folder for a test.
+    fn folder(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("drumgen_library_{}_{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn template(value: u8) -> DrumGen {
+        let mut template = DrumGen::new();
+        template.parts[0].sequence[0].set_step(0, value);
+        template
+    }
+
+    fn names(library: &Library) -> Vec<&str> {
+        library.entries.iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    #[test]
+    fn lists_templates_sorted_and_flags_invalid_files() {
+        let dir = folder("list");
+        template(1).save_file(dir.join("DGT_0002.hex")).unwrap();
+        template(2).save_file(dir.join("DGT_0001.HEX")).unwrap();
+        fs::write(dir.join("BROKEN.hex"), [0u8; 10]).unwrap();
+        fs::write(dir.join("README.txt"), "not a template").unwrap();
+
+        let library = Library::open(&dir).unwrap();
+        assert_eq!(names(&library), ["BROKEN", "DGT_0001", "DGT_0002"]);
+        assert!(library.entries[0].template.as_ref().unwrap_err().contains("192"));
+        assert_eq!(library.entries[1].template, Ok(template(2)));
+        assert!(library.entries.iter().all(|entry| entry.name_warning.is_none()));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn warns_about_names_ngen_rejects() {
+        let dir = folder("warn");
+        template(1).save_file(dir.join("my long template.hex")).unwrap();
+        let library = Library::open(&dir).unwrap();
+        assert!(library.entries[0].name_warning.as_ref().unwrap().contains("contains ` `"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rename_uppercases_and_checks_the_name() {
+        let dir = folder("rename");
+        template(1).save_file(dir.join("DGT_0001.hex")).unwrap();
+        template(2).save_file(dir.join("DGT_0002.hex")).unwrap();
+        let mut library = Library::open(&dir).unwrap();
+
+        let path = library.rename(0, "kicks").unwrap();
+        assert_eq!(path, dir.join("KICKS.HEX"));
+        assert_eq!(names(&library), ["DGT_0002", "KICKS"]);
+        assert!(matches!(library.rename(0, "TOOLONGNAME"), Err(DrumGenError::InvalidName(_))));
+        assert!(matches!(library.rename(0, "MY KICK"), Err(DrumGenError::InvalidName(_))));
+        assert!(matches!(library.rename(0, "kicks"), Err(DrumGenError::Io(_))));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn duplicate_picks_the_next_free_name() {
+        let dir = folder("duplicate");
+        template(1).save_file(dir.join("DGT_0001.hex")).unwrap();
+        template(2).save_file(dir.join("DGT_0002.hex")).unwrap();
+        template(3).save_file(dir.join("BREAKBEA.hex")).unwrap();
+        let mut library = Library::open(&dir).unwrap();
+
+        let copy = library.duplicate(1).unwrap();
+        assert_eq!(library.entries[copy].name, "DGT_0003");
+        assert_eq!(library.entries[copy].template, Ok(template(1)));
+        let copy = library.duplicate(0).unwrap();
+        assert_eq!(library.entries[copy].name, "BREAKBE1");
+
+        template(4).save_file(dir.join("99999999.hex")).unwrap();
+        template(5).save_file(dir.join("DGT_9999.hex")).unwrap();
+        library.refresh().unwrap();
+        let index = names(&library).iter().position(|name| *name == "99999999").unwrap();
+        assert!(matches!(library.duplicate(index), Err(DrumGenError::InvalidName(_))));
+        let index = names(&library).iter().position(|name| *name == "DGT_9999").unwrap();
+        let copy = library.duplicate(index).unwrap();
+        assert_eq!(library.entries[copy].name, "DGT10000");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn swap_and_delete() {
+        let dir = folder("swap");
+        template(1).save_file(dir.join("A.hex")).unwrap();
+        template(2).save_file(dir.join("B.hex")).unwrap();
+        let mut library = Library::open(&dir).unwrap();
+
+        library.swap(0, 1).unwrap();
+        assert_eq!(names(&library), ["A", "B"]);
+        assert_eq!(library.entries[0].template, Ok(template(2)));
+        assert_eq!(library.entries[1].template, Ok(template(1)));
+
+        library.delete(0).unwrap();
+        assert_eq!(names(&library), ["B"]);
+        assert!(library.delete(1).is_err());
+        assert!(library.swap(0, 1).is_err());
+        assert!(library.duplicate(1).is_err());
+        assert!(library.rename(1, "C").is_err());
+        assert!(!dir.join("A.hex").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn failed_swap_puts_the_files_back() {
+        let dir = folder("swap_fails");
+        template(1).save_file(dir.join("A.hex")).unwrap();
+        template(2).save_file(dir.join("B.hex")).unwrap();
+        fs::write(dir.join(".drumgen_swap_0.tmp"), "left over").unwrap();
+        let mut library = Library::open(&dir).unwrap();
+
+        // B disappears behind the library's back, so moving it fails half way.
+        fs::remove_file(dir.join("B.hex")).unwrap();
+        assert!(library.swap(0, 1).is_err());
+        let mut restored = DrumGen::new();
+        restored.parse_file(dir.join("A.hex")).unwrap();
+        assert_eq!(restored, template(1));
+        assert_eq!(fs::read_to_string(dir.join(".drumgen_swap_0.tmp")).unwrap(), "left over");
+        assert!(!dir.join(".drumgen_swap_1.tmp").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,5 +1,8 @@
-mod drumgen;
+#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
+mod document;
 mod gui;
+mod history;
+mod step_grid;
 
 
 fn main() {
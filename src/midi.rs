@@ -0,0 +1,476 @@
+/*
+    Standard MIDI File export and import.
+
+    Export renders a DrumGen template into a concrete drum pattern by rolling
+    every step's probability with a seeded random generator, the same way the
+    template would play on NGEN. Import goes the other way: GrooveLearner
+    quantizes MIDI drum loops onto the step grid and derives the step
+    probabilities from how often each step is hit.
+
+    Steps are 16th notes, so the 32 step template covers two bars of 4/4.
+*/
+use crate::drumgen::{DrumGen, DrumGenError, LAYER_VELOCITIES, MAX_STEP_VALUE};
+use log::info;
+use midly::num::{u15, u24, u28, u4, u7};
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::path::Path;
+use std::collections::{HashMap, HashSet};
+
+/// Number of template steps in one bar of 4/4.
+pub const STEPS_PER_BAR: usize = 16;
+/// Resolution of the exported file in ticks per quarter note.
+pub const TICKS_PER_BEAT: u16 = 96;
+/// General MIDI drum notes used for the four parts by default (kick, snare, closed and open hat).
+pub const GM_DRUM_NOTES: [u8; 4] = [36, 38, 42, 46];
+
+const TICKS_PER_STEP: u32 = TICKS_PER_BEAT as u32 / 4;
+const NOTE_LENGTH: u32 = TICKS_PER_STEP / 2;
+
+/// Options for rendering a template to MIDI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MidiExportSettings {
+    /// Seed of the random generator that rolls the step probabilities.
+    pub seed: u64,
+    /// Length of the pattern in bars of 4/4.
+    pub bars: u32,
+    /// MIDI note played by each part.
+    pub notes: [u8; 4],
+    /// MIDI velocity of each velocity layer.
+    pub velocities: [u8; 3],
+    /// MIDI channel (0-15). Channel 10 (9 here) is the General MIDI drum channel.
+    pub channel: u8,
+    /// Tempo in beats per minute.
+    pub tempo: f64,
+}
+
+impl Default for MidiExportSettings {
+    fn default() -> Self {
+        MidiExportSettings {
+            seed: 0,
+            bars: 4,
+            notes: GM_DRUM_NOTES,
+            velocities: LAYER_VELOCITIES,
+            channel: 9,
+            tempo: 120.0,
+        }
+    }
+}
+
+/// Roll the template for `bars` bars.
+///
+/// Returns, for every 16th note step, the velocity layer played by each part
+/// (`None` when the part is silent). The same seed always gives the same pattern.
+pub fn render_pattern(template: &DrumGen, seed: u64, bars: u32) -> Vec<[Option<usize>; 4]> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let steps = bars as usize * STEPS_PER_BAR;
+    let template_steps = template.parts[0].sequence[0].steps.len();
+    (0..steps)
+        .map(|step| {
+            let mut hits = [None; 4];
+            for (hit, part) in hits.iter_mut().zip(template.parts.iter()) {
+                *hit = part.roll_step(step % template_steps, &mut rng);
+            }
+            hits
+        })
+        .collect()
+}
+
+fn event(delta: u32, kind: TrackEventKind<'_>) -> TrackEvent<'_> {
+    TrackEvent { delta: u28::new(delta), kind }
+}
+
+/// Render the template to a type 1 Standard MIDI File with one track per part.
+pub fn write_midi(template: &DrumGen, settings: &MidiExportSettings) -> Result<Vec<u8>, DrumGenError> {
+    let pattern = render_pattern(template, settings.seed, settings.bars);
+    let channel = u4::new(settings.channel.min(15));
+
+    let mut smf = Smf::new(Header::new(Format::Parallel, Timing::Metrical(u15::new(TICKS_PER_BEAT))));
+    let tempo = (60_000_000.0 / settings.tempo.max(1.0)).round() as u32;
+    smf.tracks.push(vec![
+        event(0, TrackEventKind::Meta(MetaMessage::TrackName(b"DrumGen"))),
+        event(0, TrackEventKind::Meta(MetaMessage::Tempo(u24::new(tempo.min(0xFF_FFFF))))),
+        event(0, TrackEventKind::Meta(MetaMessage::TimeSignature(4, 2, 24, 8))),
+        event(0, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+    ]);
+
+    let track_names: Vec<String> = (1..=4).map(|part| format!("Part {}", part)).collect();
+    for (part, name) in track_names.iter().enumerate() {
+        let key = u7::new(settings.notes[part].min(127));
+        let mut track = vec![event(0, TrackEventKind::Meta(MetaMessage::TrackName(name.as_bytes())))];
+        let mut last_tick = 0;
+        for (step, hits) in pattern.iter().enumerate() {
+            let Some(layer) = hits[part] else { continue };
+            let tick = step as u32 * TICKS_PER_STEP;
+            let vel = u7::new(settings.velocities[layer].clamp(1, 127));
+            track.push(event(tick - last_tick, TrackEventKind::Midi { channel, message: MidiMessage::NoteOn { key, vel } }));
+            track.push(event(NOTE_LENGTH, TrackEventKind::Midi { channel, message: MidiMessage::NoteOff { key, vel: u7::new(0) } }));
+            last_tick = tick + NOTE_LENGTH;
+        }
+        let end = pattern.len() as u32 * TICKS_PER_STEP;
+        track.push(event(end.saturating_sub(last_tick), TrackEventKind::Meta(MetaMessage::EndOfTrack)));
+        smf.tracks.push(track);
+    }
+
+    let mut data = Vec::new();
+    smf.write_std(&mut data)?;
+    Ok(data)
+}
+
+impl DrumGen {
+    /// Render the template with [`write_midi`] and save it as a .mid file.
+    pub fn save_midi_file<P: AsRef<Path>>(&self, filepath: P, settings: &MidiExportSettings) -> Result<(), DrumGenError> {
+        let filepath = filepath.as_ref();
+        let data = write_midi(self, settings)?;
+        info!("Saving MIDI file to: {}", filepath.display());
+        std::fs::write(filepath, data)?;
+        Ok(())
+    }
+}
+
+/// Options for learning a template from MIDI files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MidiLearnSettings {
+    /// MIDI notes mapped to each part. Notes that aren't listed are ignored.
+    pub notes: [Vec<u8>; 4],
+    /// Velocity of each layer. Hits go to the layer with the closest velocity.
+    pub velocities: [u8; 3],
+}
+
+impl Default for MidiLearnSettings {
+    fn default() -> Self {
+        MidiLearnSettings {
+            notes: [
+                vec![35, 36],         // kicks
+                vec![37, 38, 39, 40], // rimshot, snares, clap
+                vec![42, 44],         // closed and pedal hi-hat
+                vec![46, 49, 51, 57], // open hi-hat, crash and ride
+            ],
+            velocities: LAYER_VELOCITIES,
+        }
+    }
+}
+
+impl MidiLearnSettings {
+    fn part_for_note(&self, note: u8) -> Option<usize> {
+        self.notes.iter().position(|notes| notes.contains(&note))
+    }
+
+    fn layer_for_velocity(&self, velocity: u8) -> usize {
+        (0..self.velocities.len())
+            .min_by_key(|layer| (self.velocities[*layer] as i16 - velocity as i16).abs())
+            .unwrap_or(0)
+    }
+}
+
+// Template length in 16th note steps.
+const CYCLE_STEPS: usize = 32;
+
+/// Builds a template from the statistics of one or more MIDI drum loops.
+///
+/// Every loop is cut into 32 step cycles (shorter loops are repeated to fill a
+/// cycle) and each part / layer / step is set to how often it was hit across
+/// all cycles, on the 0 - 10 scale. When a part is hit on several layers in the
+/// same step, only the loudest one counts, and the softer layers are scaled to
+/// account for the louder ones being tried first (see
+/// [`DrumGenLayer::roll_step`](crate::DrumGenLayer::roll_step)). Rendering the
+/// learned template then reproduces the observed hit rates.
+#[derive(Debug, Clone)]
+pub struct GrooveLearner {
+    settings: MidiLearnSettings,
+    hits: [[[u32; CYCLE_STEPS]; 3]; 4],
+    cycles: u32,
+}
+
+impl GrooveLearner {
+    pub fn new(settings: MidiLearnSettings) -> GrooveLearner {
+        GrooveLearner {
+            settings,
+            hits: [[[0; CYCLE_STEPS]; 3]; 4],
+            cycles: 0,
+        }
+    }
+
+    /// Number of 32 step cycles learned so far.
+    pub fn cycles(&self) -> u32 {
+        self.cycles
+    }
+
+    /// Learn from the contents of a Standard MIDI File.
+    pub fn add_midi(&mut self, data: &[u8]) -> Result<(), DrumGenError> {
+        let smf = Smf::parse(data).map_err(|err| DrumGenError::InvalidMidi(err.to_string()))?;
+        let ticks_per_beat = match smf.header.timing {
+            Timing::Metrical(ticks) => ticks.as_int() as u64,
+            Timing::Timecode(..) => {
+                return Err(DrumGenError::InvalidMidi("SMPTE timing is not supported".to_string()));
+            }
+        };
+        let ticks_per_step = (ticks_per_beat / 4).max(1);
+
+        // Mapped hits as (part, tick, layer), and the end of the loop.
+        let mut hits = Vec::new();
+        let mut end_tick = 0;
+        for track in &smf.tracks {
+            let mut tick = 0u64;
+            for event in track {
+                tick += event.delta.as_int() as u64;
+                if let TrackEventKind::Midi { message: MidiMessage::NoteOn { key, vel }, .. } = event.kind {
+                    if vel.as_int() == 0 {
+                        continue;
+                    }
+                    if let Some(part) = self.settings.part_for_note(key.as_int()) {
+                        hits.push((part, tick, self.settings.layer_for_velocity(vel.as_int())));
+                        end_tick = end_tick.max(tick + 1);
+                    }
+                }
+            }
+            end_tick = end_tick.max(tick);
+        }
+        if hits.is_empty() {
+            return Err(DrumGenError::InvalidMidi("no notes mapped to a part".to_string()));
+        }
+
+        // Round the loop up to whole bars. The length comes from the unquantized
+        // ticks, so a slightly early hit at the very end wraps around to step 0
+        // instead of making the loop a bar longer.
+        let bar_steps = STEPS_PER_BAR as u64;
+        let loop_steps = end_tick.div_ceil(ticks_per_step).div_ceil(bar_steps).max(1) * bar_steps;
+
+        // Loudest layer hit by each part at each quantized step of the loop.
+        let mut loudest: HashMap<(usize, u64), usize> = HashMap::new();
+        for (part, tick, layer) in hits {
+            let step = (tick + ticks_per_step / 2) / ticks_per_step % loop_steps;
+            let loudest_layer = loudest.entry((part, step)).or_insert(layer);
+            *loudest_layer = (*loudest_layer).min(layer);
+        }
+
+        // Repeat the loop until it fills whole cycles.
+        let total_steps = lcm(loop_steps, CYCLE_STEPS as u64);
+        let mut seen = HashSet::new();
+        for ((part, step), layer) in loudest {
+            let mut position = step;
+            while position < total_steps {
+                if seen.insert((part, position)) {
+                    self.hits[part][layer][(position % CYCLE_STEPS as u64) as usize] += 1;
+                }
+                position += loop_steps;
+            }
+        }
+        self.cycles += (total_steps / CYCLE_STEPS as u64) as u32;
+        Ok(())
+    }
+
+    /// Learn from a .mid file.
+    pub fn add_file<P: AsRef<Path>>(&mut self, filepath: P) -> Result<(), DrumGenError> {
+        let filepath = filepath.as_ref();
+        info!("Learning from MIDI file: {}", filepath.display());
+        self.add_midi(&std::fs::read(filepath)?)
+    }
+
+    /// The template learned so far.
+    pub fn template(&self) -> DrumGen {
+        let mut template = DrumGen::new();
+        if self.cycles == 0 {
+            return template;
+        }
+        for (part, layers) in template.parts.iter_mut().enumerate() {
+            for step in 0..CYCLE_STEPS {
+                // Chance that no louder layer played before this one.
+                let mut remaining = 1.0;
+                for layer in 0..3 {
+                    let rate = self.hits[part][layer][step] as f64 / self.cycles as f64;
+                    let probability = if remaining > 0.0 { (rate / remaining).min(1.0) } else { 0.0 };
+                    let value = (probability * MAX_STEP_VALUE as f64).round() as u8;
+                    layers.sequence[layer].set_step(step, value);
+                    remaining -= rate;
+                }
+            }
+        }
+        template
+    }
+}
+
+fn lcm(a: u64, b: u64) -> u64 {
+    let (mut x, mut y) = (a, b);
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+    a / x * b
+}
+
+/// Learn a template from several .mid files with [`GrooveLearner`].
+pub fn learn_template<P: AsRef<Path>>(files: &[P], settings: &MidiLearnSettings) -> Result<DrumGen, DrumGenError> {
+    let mut learner = GrooveLearner::new(settings.clone());
+    for file in files {
+        learner.add_file(file)?;
+    }
+    Ok(learner.template())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template() -> DrumGen {
+        let mut template = DrumGen::new();
+        for i in (0..32).step_by(4) {
+            template.parts[0].sequence[0].set_step(i, 10);
+        }
+        for i in 0..32 {
+            template.parts[2].sequence[1].set_step(i, 5);
+        }
+        template.parts[1].sequence[2].set_step(4, 10);
+        template.parts[1].sequence[1].set_step(12, 10);
+        template.parts[1].sequence[2].set_step(12, 10);
+        template
+    }
+
+    #[test]
+    fn pattern_follows_probabilities() {
+        let pattern = render_pattern(&template(), 42, 8);
+        assert_eq!(pattern.len(), 8 * STEPS_PER_BAR);
+        for (step, hits) in pattern.iter().enumerate() {
+            assert_eq!(hits[0], if step % 4 == 0 { Some(0) } else { None });
+            assert_eq!(hits[3], None);
+            assert!(hits[2].is_none() || hits[2] == Some(1));
+        }
+        assert_eq!(pattern[4][1], Some(2));
+        // The louder layer wins when several layers hit on the same step.
+        assert_eq!(pattern[12][1], Some(1));
+
+        let hat_hits = pattern.iter().filter(|hits| hits[2].is_some()).count();
+        assert!((32..96).contains(&hat_hits), "{} hits at 50%", hat_hits);
+    }
+
+    #[test]
+    fn pattern_is_deterministic_for_a_seed() {
+        let template = template();
+        assert_eq!(render_pattern(&template, 7, 4), render_pattern(&template, 7, 4));
+        assert_ne!(render_pattern(&template, 7, 4), render_pattern(&template, 8, 4));
+        assert_eq!(render_pattern(&template, 7, 2), render_pattern(&template, 7, 4)[..32]);
+    }
+
+    #[test]
+    fn midi_file_has_a_track_per_part() {
+        let settings = MidiExportSettings { seed: 3, bars: 2, ..Default::default() };
+        let data = write_midi(&template(), &settings).unwrap();
+        let smf = Smf::parse(&data).unwrap();
+        assert_eq!(smf.header.format, Format::Parallel);
+        assert_eq!(smf.tracks.len(), 5);
+
+        let note_ons = |track: &Vec<TrackEvent>| -> Vec<(u32, u8, u8)> {
+            let mut tick = 0;
+            let mut notes = Vec::new();
+            for event in track {
+                tick += event.delta.as_int();
+                if let TrackEventKind::Midi { channel, message: MidiMessage::NoteOn { key, vel } } = event.kind {
+                    assert_eq!(channel.as_int(), 9);
+                    notes.push((tick, key.as_int(), vel.as_int()));
+                }
+            }
+            notes
+        };
+        let kicks = note_ons(&smf.tracks[1]);
+        assert_eq!(kicks.len(), 8);
+        assert!(kicks.iter().enumerate().all(|(i, note)| *note == (i as u32 * 4 * TICKS_PER_STEP, 36, 127)));
+
+        let snares = note_ons(&smf.tracks[2]);
+        assert_eq!(snares[0], (4 * TICKS_PER_STEP, 38, 60));
+        assert_eq!(snares[1], (12 * TICKS_PER_STEP, 38, 100));
+        assert!(note_ons(&smf.tracks[4]).is_empty());
+    }
+
+    fn drum_loop(steps: usize, hits: &[(usize, u8, u8)]) -> Vec<u8> {
+        let mut smf = Smf::new(Header::new(Format::SingleTrack, Timing::Metrical(u15::new(480))));
+        let mut track = Vec::new();
+        let mut last_tick = 0;
+        let mut sorted = hits.to_vec();
+        sorted.sort();
+        for (step, key, vel) in sorted {
+            // Slightly early or late hits still land on their step.
+            let tick = (step as u32 * 120).saturating_sub(if step % 2 == 0 { 10 } else { 0 }) + if step % 2 == 1 { 20 } else { 0 };
+            track.push(event(tick - last_tick, TrackEventKind::Midi { channel: u4::new(9), message: MidiMessage::NoteOn { key: u7::new(key), vel: u7::new(vel) } }));
+            last_tick = tick;
+        }
+        track.push(event(steps as u32 * 120 - last_tick, TrackEventKind::Meta(MetaMessage::EndOfTrack)));
+        smf.tracks.push(track);
+        let mut data = Vec::new();
+        smf.write_std(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn learning_a_rendered_template_recovers_it() {
+        let mut template = DrumGen::new();
+        for i in (0..32).step_by(4) {
+            template.parts[0].sequence[0].set_step(i, 10);
+        }
+        template.parts[1].sequence[1].set_step(4, 10);
+        template.parts[1].sequence[2].set_step(12, 10);
+        template.parts[2].sequence[2].set_step(31, 10);
+        let settings = MidiExportSettings { bars: 4, ..Default::default() };
+        let mut learner = GrooveLearner::new(MidiLearnSettings::default());
+        learner.add_midi(&write_midi(&template, &settings).unwrap()).unwrap();
+        assert_eq!(learner.cycles(), 2);
+        assert_eq!(learner.template(), template);
+    }
+
+    #[test]
+    fn learning_averages_hits_over_cycles() {
+        let mut learner = GrooveLearner::new(MidiLearnSettings::default());
+        // Four bars: the kick on step 0 of both cycles, the snare only in the first.
+        learner.add_midi(&drum_loop(64, &[(0, 36, 120), (32, 36, 127), (8, 38, 100)])).unwrap();
+        // Another two bars with the snare again on a different note.
+        learner.add_midi(&drum_loop(32, &[(8, 40, 95), (0, 36, 64)])).unwrap();
+        assert_eq!(learner.cycles(), 3);
+
+        let template = learner.template();
+        assert_eq!(template.parts[0].sequence[0].get_step(0), 7);
+        // The soft kick only plays when the loud one misses: (1/3) / (1 - 2/3).
+        assert_eq!(template.parts[0].sequence[2].get_step(0), 10);
+        assert_eq!(template.parts[1].sequence[1].get_step(8), 7);
+        assert_eq!(template.parts[1].sequence[0].get_step(8), 0);
+    }
+
+    #[test]
+    fn learning_repeats_short_loops() {
+        let mut learner = GrooveLearner::new(MidiLearnSettings::default());
+        learner.add_midi(&drum_loop(16, &[(0, 36, 127), (4, 42, 60), (15, 46, 100)])).unwrap();
+        assert_eq!(learner.cycles(), 1);
+        let template = learner.template();
+        assert_eq!(template.parts[0].sequence[0].get_step(16), 10);
+        assert_eq!(template.parts[2].sequence[2].get_step(20), 10);
+        assert_eq!(template.parts[3].sequence[1].get_step(31), 10);
+
+        // A three bar loop fills three cycles.
+        let mut learner = GrooveLearner::new(MidiLearnSettings::default());
+        learner.add_midi(&drum_loop(48, &[(40, 36, 127)])).unwrap();
+        assert_eq!(learner.cycles(), 3);
+        let template = learner.template();
+        assert_eq!(template.parts[0].sequence[0].get_step(8), 3);
+        assert_eq!(template.parts[0].sequence[0].get_step(24), 3);
+        assert_eq!(template.parts[0].sequence[0].get_step(0), 0);
+    }
+
+    #[test]
+    fn early_downbeat_at_the_loop_end_wraps_to_the_first_step() {
+        let mut learner = GrooveLearner::new(MidiLearnSettings::default());
+        // Two bars with kicks on steps 0 and 16, and a softer one pushed 10 ticks before the loop ends.
+        learner.add_midi(&drum_loop(32, &[(0, 36, 127), (16, 36, 127), (32, 36, 100)])).unwrap();
+        assert_eq!(learner.cycles(), 1);
+        let template = learner.template();
+        assert_eq!(template.parts[0].sequence[0].get_step(0), 10);
+        assert_eq!(template.parts[0].sequence[0].get_step(16), 10);
+        assert!(template.parts[0].sequence[1].steps.iter().all(|step| *step == 0));
+    }
+
+    #[test]
+    fn learning_rejects_invalid_files() {
+        let mut learner = GrooveLearner::new(MidiLearnSettings::default());
+        assert!(matches!(learner.add_midi(b"not a midi file"), Err(DrumGenError::InvalidMidi(_))));
+        assert!(matches!(learner.add_midi(&drum_loop(16, &[(0, 60, 100)])), Err(DrumGenError::InvalidMidi(_))));
+        assert_eq!(learner.cycles(), 0);
+        assert_eq!(learner.template(), DrumGen::new());
+    }
+}
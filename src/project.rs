@@ -0,0 +1,238 @@
+/*
+    Extended project format.
+
+    A DrumGenProject wraps a template with metadata that the 192 byte .hex
+    format can't hold (name, author, tags, layer velocities and notes). The
+    locked steps of each sequence are only kept here as well.
+    With the `json`, `toml` and `yaml` features it can be written to and read
+    from those formats; the format is picked from the file extension.
+*/
+use crate::drumgen::{DrumGen, DrumGenError, LAYER_VELOCITIES};
+use std::fs;
+use std::path::Path;
+use log::info;
+
+/// Information about a template that is not stored in the .hex file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct TemplateMetadata {
+    pub name: String,
+    pub author: String,
+    pub tags: Vec<String>,
+    /// MIDI velocity of each of the three layers of a part.
+    pub velocities: [u8; 3],
+    pub notes: String,
+}
+
+impl Default for TemplateMetadata {
+    fn default() -> Self {
+        TemplateMetadata {
+            name: String::new(),
+            author: String::new(),
+            tags: Vec::new(),
+            velocities: LAYER_VELOCITIES,
+            notes: String::new(),
+        }
+    }
+}
+
+/// A template together with its metadata.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DrumGenProject {
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub metadata: TemplateMetadata,
+    pub template: DrumGen,
+}
+
+/// Serialization formats supported by [`DrumGenProject`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProjectFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ProjectFormat {
+    /// Pick the format from a file extension (`json`, `toml`, `yaml` or `yml`).
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<ProjectFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(ProjectFormat::Json),
+            "toml" => Some(ProjectFormat::Toml),
+            "yaml" | "yml" => Some(ProjectFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    /// File extensions of the formats enabled in this build.
+    pub fn enabled_extensions() -> Vec<&'static str> {
+        let mut extensions = Vec::new();
+        if cfg!(feature = "json") {
+            extensions.push("json");
+        }
+        if cfg!(feature = "toml") {
+            extensions.push("toml");
+        }
+        if cfg!(feature = "yaml") {
+            extensions.extend(["yaml", "yml"]);
+        }
+        extensions
+    }
+}
+
+#[cfg(not(all(feature = "json", feature = "toml", feature = "yaml")))]
+fn disabled(format: ProjectFormat) -> String {
+    format!("{:?} support is not enabled in this build", format)
+}
+
+impl DrumGenProject {
+    /// Create a project for a template with default metadata.
+    pub fn new(template: DrumGen) -> DrumGenProject {
+        DrumGenProject {
+            metadata: TemplateMetadata::default(),
+            template,
+        }
+    }
+
+    /// Serialize the project.
+    pub fn to_string_as(&self, format: ProjectFormat) -> Result<String, DrumGenError> {
+        self.template.validate()?;
+        match format {
+            #[cfg(feature = "json")]
+            ProjectFormat::Json => serde_json::to_string_pretty(self).map_err(|err| DrumGenError::Format(err.to_string())),
+            #[cfg(feature = "toml")]
+            ProjectFormat::Toml => toml::to_string_pretty(self).map_err(|err| DrumGenError::Format(err.to_string())),
+            #[cfg(feature = "yaml")]
+            ProjectFormat::Yaml => serde_yaml::to_string(self).map_err(|err| DrumGenError::Format(err.to_string())),
+            #[cfg(not(all(feature = "json", feature = "toml", feature = "yaml")))]
+            _ => Err(DrumGenError::Format(disabled(format))),
+        }
+    }
+
+    /// Deserialize a project, rejecting step values outside of 0-10.
+    pub fn from_str_as(text: &str, format: ProjectFormat) -> Result<DrumGenProject, DrumGenError> {
+        let result: Result<DrumGenProject, String> = match format {
+            #[cfg(feature = "json")]
+            ProjectFormat::Json => serde_json::from_str(text).map_err(|err| err.to_string()),
+            #[cfg(feature = "toml")]
+            ProjectFormat::Toml => toml::from_str(text).map_err(|err| err.to_string()),
+            #[cfg(feature = "yaml")]
+            ProjectFormat::Yaml => serde_yaml::from_str(text).map_err(|err| err.to_string()),
+            #[cfg(not(all(feature = "json", feature = "toml", feature = "yaml")))]
+            _ => {
+                // Unused when no format is enabled.
+                let _ = text;
+                Err(disabled(format))
+            }
+        };
+        let project = result.map_err(DrumGenError::Format)?;
+        project.template.validate()?;
+        Ok(project)
+    }
+
+    /// Write the project to a file, picking the format from its extension.
+    pub fn save_file<P: AsRef<Path>>(&self, filepath: P) -> Result<(), DrumGenError> {
+        let filepath = filepath.as_ref();
+        let format = format_for(filepath)?;
+        info!("Saving project to: {}", filepath.display());
+        fs::write(filepath, self.to_string_as(format)?)?;
+        Ok(())
+    }
+
+    /// Read a project file, picking the format from its extension.
+    pub fn parse_file<P: AsRef<Path>>(filepath: P) -> Result<DrumGenProject, DrumGenError> {
+        let filepath = filepath.as_ref();
+        let format = format_for(filepath)?;
+        info!("Parsing project: {}", filepath.display());
+        DrumGenProject::from_str_as(&fs::read_to_string(filepath)?, format)
+    }
+}
+
+fn format_for(filepath: &Path) -> Result<ProjectFormat, DrumGenError> {
+    ProjectFormat::from_path(filepath).ok_or_else(|| {
+        DrumGenError::Format(format!("unknown project extension for {} (expected .json, .toml or .yaml)", filepath.display()))
+    })
+}
+
+#[cfg(all(test, feature = "json", feature = "toml", feature = "yaml"))]
+mod tests {
+    use super::*;
+    use crate::drumgen::StepMask;
+
+    fn project() -> DrumGenProject {
+        let mut template = DrumGen::new();
+        template.parts[0].sequence[0].set_step(0, 10);
+        template.parts[2].sequence[1].set_step(13, 4);
+        template.parts[3].sequence[2].set_step(31, 7);
+        template.parts[0].sequence[0].set_locked(StepMask(1));
+        template.parts[3].sequence[2].set_locked(StepMask(1 << 31));
+        DrumGenProject {
+            metadata: TemplateMetadata {
+                name: "DGT_0001".to_string(),
+                author: "Spektro Audio".to_string(),
+                tags: vec!["house".to_string(), "four on the floor".to_string()],
+                velocities: [120, 90, 40],
+                notes: "Kick on every beat.\nGhost snares on layer 3.".to_string(),
+            },
+            template,
+        }
+    }
+
+    #[test]
+    fn project_round_trips_every_format() {
+        let project = project();
+        for format in [ProjectFormat::Json, ProjectFormat::Toml, ProjectFormat::Yaml] {
+            let text = project.to_string_as(format).unwrap();
+            assert_eq!(DrumGenProject::from_str_as(&text, format).unwrap(), project, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn locks_are_only_written_when_set() {
+        let project = project();
+        let json = project.to_string_as(ProjectFormat::Json).unwrap();
+        assert_eq!(json.matches("\"locked\"").count(), 2);
+        let loaded = DrumGenProject::from_str_as(&json, ProjectFormat::Json).unwrap();
+        assert!(loaded.template.parts[3].sequence[2].locked().contains(31));
+    }
+
+    #[test]
+    fn missing_metadata_uses_defaults() {
+        let template = project().template;
+        let json = format!("{{\"template\": {}}}", serde_json::to_string(&template).unwrap());
+        let project = DrumGenProject::from_str_as(&json, ProjectFormat::Json).unwrap();
+        assert_eq!(project.metadata, TemplateMetadata::default());
+        assert_eq!(project.metadata.velocities, LAYER_VELOCITIES);
+        assert_eq!(project.template, template);
+    }
+
+    #[test]
+    fn from_str_rejects_out_of_range_steps() {
+        let mut project = project();
+        project.template.parts[1].sequence[2].steps[5] = 11;
+        let json = serde_json::to_string(&project).unwrap();
+        assert!(matches!(
+            DrumGenProject::from_str_as(&json, ProjectFormat::Json),
+            Err(DrumGenError::InvalidStep { part: 1, layer: 2, step: 5, value: 11 })
+        ));
+        assert!(project.to_string_as(ProjectFormat::Json).is_err());
+    }
+
+    #[test]
+    fn save_file_picks_format_from_extension() {
+        let project = project();
+        for extension in ["json", "toml", "yml"] {
+            let path = std::env::temp_dir().join(format!("drumgen_project_{}.{}", std::process::id(), extension));
+            project.save_file(&path).unwrap();
+            let loaded = DrumGenProject::parse_file(&path);
+            let _ = fs::remove_file(&path);
+            assert_eq!(loaded.unwrap(), project);
+        }
+        assert!(matches!(
+            project.save_file(std::env::temp_dir().join("project.hex")),
+            Err(DrumGenError::Format(_))
+        ));
+    }
+}
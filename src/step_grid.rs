@@ -0,0 +1,262 @@
+/*
+    Step grid widget.
+
+    Draws the 32 steps of a sequence as bars in a single painter-drawn control,
+    grouped by beat. Dragging across the grid draws values along the mouse
+    path; the line and curve tools draw from where the drag started to the
+    pointer instead.
+*/
+use eframe::egui::{self, Color32, Id, Pos2, Rect, Response, Sense, Stroke, Ui, Widget};
+use drumgen::{StepMask, MAX_STEP_VALUE, STEP_COUNT};
+use std::ops::Range;
+
+const STEP_WIDTH: f32 = 20.0;
+const BEAT_GAP: f32 = 10.0;
+const BAR_HEIGHT: f32 = 100.0;
+const LABEL_HEIGHT: f32 = 14.0;
+const STEPS_PER_BEAT: usize = 4;
+
+/// How dragging over the grid changes the steps.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Tool {
+    /// Set each step the pointer passes over.
+    Paint,
+    /// Draw a straight line from where the drag started.
+    Line,
+    /// Draw a curve from where the drag started, bent by the grid's bend amount.
+    Curve,
+}
+
+// State of a drag, kept in egui's memory between frames.
+#[derive(Clone)]
+struct GridDrag {
+    start: (usize, u8),
+    last: (usize, u8),
+    // Steps before the drag, so line and curve previews can be redrawn every frame.
+    original: [u8; STEP_COUNT],
+}
+
+pub struct StepGrid<'a> {
+    id_source: Id,
+    steps: &'a mut [u8; STEP_COUNT],
+    tool: Tool,
+    bend: f32,
+    editable: bool,
+    highlight: Option<Range<usize>>,
+    locked: StepMask,
+}
+
+impl<'a> StepGrid<'a> {
+    pub fn new(id_source: impl std::hash::Hash, steps: &'a mut [u8; STEP_COUNT]) -> Self {
+        StepGrid {
+            id_source: Id::new(id_source),
+            steps,
+            tool: Tool::Paint,
+            bend: 0.0,
+            editable: true,
+            highlight: None,
+            locked: StepMask::NONE,
+        }
+    }
+
+    /// Drawing tool. `bend` (-1.0 to 1.0) shapes the curve tool: positive values
+    /// start slowly and rise late, negative values rise early.
+    pub fn tool(mut self, tool: Tool, bend: f32) -> Self {
+        self.tool = tool;
+        self.bend = bend.clamp(-1.0, 1.0);
+        self
+    }
+
+    /// When false the grid only reports hovering, e.g. while selecting steps.
+    pub fn editable(mut self, editable: bool) -> Self {
+        self.editable = editable;
+        self
+    }
+
+    /// Steps to draw as selected.
+    pub fn highlight(mut self, steps: Option<Range<usize>>) -> Self {
+        self.highlight = steps;
+        self
+    }
+
+    /// Steps to mark with a lock. Drawing leaves them as they are.
+    pub fn locked(mut self, locked: StepMask) -> Self {
+        self.locked = locked;
+        self
+    }
+
+    fn draw(&mut self, drag: &GridDrag, step: usize, value: u8) {
+        match self.tool {
+            Tool::Paint => {
+                // Fill every step between the last pointer position and this one.
+                let (last_step, last_value) = drag.last;
+                draw_line(self.steps, self.locked, (last_step, last_value), (step, value), |t| t);
+            }
+            Tool::Line => {
+                *self.steps = drag.original;
+                draw_line(self.steps, self.locked, drag.start, (step, value), |t| t);
+            }
+            Tool::Curve => {
+                *self.steps = drag.original;
+                let exponent = 4f32.powf(self.bend);
+                draw_line(self.steps, self.locked, drag.start, (step, value), |t| t.powf(exponent));
+            }
+        }
+    }
+}
+
+// Set the steps from `from` to `to`, shaping the values in between with `curve` (0.0-1.0 to 0.0-1.0).
+// Locked steps are skipped.
+fn draw_line(steps: &mut [u8; STEP_COUNT], locked: StepMask, from: (usize, u8), to: (usize, u8), curve: impl Fn(f32) -> f32) {
+    let ((first, first_value), (last, last_value)) = if from.0 <= to.0 { (from, to) } else { (to, from) };
+    let length = (last - first).max(1) as f32;
+    for (offset, step) in (first..=last).enumerate().filter(|&(_, step)| !locked.contains(step)) {
+        let t = curve(offset as f32 / length);
+        let value = first_value as f32 + (last_value as f32 - first_value as f32) * t;
+        steps[step] = value.round().clamp(0.0, MAX_STEP_VALUE as f32) as u8;
+    }
+}
+
+/// Size of the grid.
+pub fn size() -> egui::Vec2 {
+    let beats = (STEP_COUNT / STEPS_PER_BEAT) as f32;
+    egui::vec2(STEP_COUNT as f32 * STEP_WIDTH + (beats - 1.0) * BEAT_GAP, BAR_HEIGHT + LABEL_HEIGHT)
+}
+
+// Area of one step's bar.
+fn step_rect(rect: Rect, step: usize) -> Rect {
+    let x = rect.left() + step as f32 * STEP_WIDTH + (step / STEPS_PER_BEAT) as f32 * BEAT_GAP;
+    Rect::from_min_size(egui::pos2(x, rect.top()), egui::vec2(STEP_WIDTH, BAR_HEIGHT))
+}
+
+/// Step under a position of a grid drawn in `rect`, clamped to the first and last step.
+pub fn step_at(rect: Rect, pos: Pos2) -> usize {
+    let beat_width = STEPS_PER_BEAT as f32 * STEP_WIDTH + BEAT_GAP;
+    let x = (pos.x - rect.left()).max(0.0);
+    let beat = (x / beat_width) as usize;
+    let in_beat = (((x - beat as f32 * beat_width) / STEP_WIDTH) as usize).min(STEPS_PER_BEAT - 1);
+    (beat * STEPS_PER_BEAT + in_beat).min(STEP_COUNT - 1)
+}
+
+// Step value for the height of a position.
+fn value_at(rect: Rect, pos: Pos2) -> u8 {
+    let value = (rect.top() + BAR_HEIGHT - pos.y) / BAR_HEIGHT * MAX_STEP_VALUE as f32;
+    value.round().clamp(0.0, MAX_STEP_VALUE as f32) as u8
+}
+
+impl Widget for StepGrid<'_> {
+    fn ui(mut self, ui: &mut Ui) -> Response {
+        let sense = if self.editable { Sense::click_and_drag() } else { Sense::hover() };
+        let (rect, mut response) = ui.allocate_exact_size(size(), sense);
+        let id = ui.make_persistent_id(self.id_source);
+
+        if self.editable {
+            if let Some(pos) = response.interact_pointer_pos() {
+                let (step, value) = (step_at(rect, pos), value_at(rect, pos));
+                let mut drag = ui.data(|data| data.get_temp::<GridDrag>(id)).unwrap_or(GridDrag {
+                    start: (step, value),
+                    last: (step, value),
+                    original: *self.steps,
+                });
+                let before = *self.steps;
+                self.draw(&drag, step, value);
+                if *self.steps != before {
+                    response.mark_changed();
+                }
+                drag.last = (step, value);
+                ui.data_mut(|data| data.insert_temp(id, drag));
+            }
+            if !ui.input(|i| i.pointer.any_down()) {
+                ui.data_mut(|data| data.remove::<GridDrag>(id));
+            }
+        }
+
+        if ui.is_rect_visible(rect) {
+            let painter = ui.painter_at(rect);
+            let visuals = ui.visuals();
+            let fill = Color32::from_rgb(115, 193, 173);
+            let hovered = response.hover_pos().map(|pos| step_at(rect, pos));
+            for (step, value) in self.steps.iter().enumerate() {
+                let cell = step_rect(rect, step).shrink2(egui::vec2(2.0, 0.0));
+                painter.rect_filled(cell, 2.0, visuals.extreme_bg_color);
+                if self.highlight.as_ref().is_some_and(|steps| steps.contains(&step)) {
+                    painter.rect_filled(cell, 2.0, fill.gamma_multiply(0.25));
+                }
+                let height = cell.height() * *value.min(&MAX_STEP_VALUE) as f32 / MAX_STEP_VALUE as f32;
+                let bar = Rect::from_min_max(egui::pos2(cell.left(), cell.bottom() - height), cell.max);
+                painter.rect_filled(bar, 2.0, fill);
+                if self.locked.contains(step) {
+                    painter.text(
+                        egui::pos2(cell.center().x, cell.top() + 2.0),
+                        egui::Align2::CENTER_TOP,
+                        "🔒",
+                        egui::FontId::proportional(10.0),
+                        visuals.strong_text_color(),
+                    );
+                }
+                if hovered == Some(step) {
+                    painter.rect_stroke(cell, 2.0, Stroke::new(1.0, visuals.strong_text_color()));
+                }
+                if step % STEPS_PER_BEAT == 0 {
+                    painter.text(
+                        egui::pos2(cell.left(), cell.bottom() + 1.0),
+                        egui::Align2::LEFT_TOP,
+                        format!("{}", step + 1),
+                        egui::FontId::proportional(10.0),
+                        visuals.weak_text_color(),
+                    );
+                }
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draw_line_interpolates_in_either_direction() {
+        let mut steps = [0; STEP_COUNT];
+        draw_line(&mut steps, StepMask::NONE, (10, 10), (0, 0), |t| t);
+        assert_eq!(&steps[..12], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 0]);
+        draw_line(&mut steps, StepMask::NONE, (3, 7), (3, 2), |t| t);
+        assert_eq!(steps[3], 7);
+    }
+
+    #[test]
+    fn every_tool_leaves_locked_steps_alone() {
+        let mut locked = StepMask::NONE;
+        locked.set(2, true);
+        locked.set(5, true);
+        for tool in [Tool::Paint, Tool::Line, Tool::Curve] {
+            let mut steps = [3; STEP_COUNT];
+            let drag = GridDrag { start: (0, 10), last: (0, 10), original: steps };
+            StepGrid::new("grid", &mut steps).tool(tool, 0.5).locked(locked).draw(&drag, 8, 10);
+            assert_eq!((steps[2], steps[5]), (3, 3), "{:?}", tool);
+            assert_eq!((steps[1], steps[8]), (10, 10), "{:?}", tool);
+        }
+    }
+
+    #[test]
+    fn step_at_accounts_for_beat_gaps() {
+        let rect = Rect::from_min_size(Pos2::ZERO, size());
+        assert_eq!(step_at(rect, egui::pos2(1.0, 0.0)), 0);
+        assert_eq!(step_at(rect, egui::pos2(4.0 * STEP_WIDTH + 1.0, 0.0)), 3);
+        assert_eq!(step_at(rect, egui::pos2(4.0 * STEP_WIDTH + BEAT_GAP + 1.0, 0.0)), 4);
+        assert_eq!(step_at(rect, egui::pos2(-50.0, 0.0)), 0);
+        assert_eq!(step_at(rect, egui::pos2(10_000.0, 0.0)), STEP_COUNT - 1);
+        for step in 0..STEP_COUNT {
+            assert_eq!(step_at(rect, step_rect(rect, step).center()), step);
+        }
+    }
+
+    #[test]
+    fn value_follows_height() {
+        let rect = Rect::from_min_size(Pos2::ZERO, size());
+        assert_eq!(value_at(rect, egui::pos2(0.0, 0.0)), 10);
+        assert_eq!(value_at(rect, egui::pos2(0.0, BAR_HEIGHT / 2.0)), 5);
+        assert_eq!(value_at(rect, egui::pos2(0.0, BAR_HEIGHT + 5.0)), 0);
+    }
+}
@@ -0,0 +1,408 @@
+/*
+    Plain-text representation of a DrumGen template.
+
+    One line per part / velocity layer, with the 32 steps written as single
+    characters (0-9, A = 10) grouped by beat:
+
+        # DrumGen template
+        name: DGT_0001
+        1:127 | A000 A000 A000 A000 A000 A000 A000 A000
+        1:100 | 0000 0000 0000 0000 0000 0000 0000 0000
+        1:60  | 0000 0000 0000 0000 0000 0000 0000 0000
+        2:127 | ...
+
+    Blank lines and lines starting with # are ignored. The name header is optional.
+
+    Parts of a template (a range of steps, a layer or a whole part) are copied
+    to the clipboard as a StepClip, which uses the same line layout labelled
+    with the velocity of each layer:
+
+        # DrumGen steps
+        127 | A000 A000
+        100 | 0050 0050
+*/
+use crate::drumgen::{clamp_steps, DrumGen, DrumGenError, DrumGenLayer, LAYER_VELOCITIES, MAX_STEP_VALUE, STEP_COUNT};
+use std::ops::Range;
+
+const HEADER: &str = "# DrumGen template";
+const CLIP_HEADER: &str = "# DrumGen steps";
+const NAME_KEY: &str = "name:";
+
+/// Character used for a step value in the text format (0-9, A = 10).
+pub fn step_char(value: u8) -> char {
+    std::char::from_digit(value.min(MAX_STEP_VALUE) as u32, 16)
+        .unwrap_or('0')
+        .to_ascii_uppercase()
+}
+
+/// Parse a step character written by [`step_char`].
+pub fn parse_step_char(c: char) -> Option<u8> {
+    c.to_digit(16)
+        .map(|value| value as u8)
+        .filter(|value| *value <= MAX_STEP_VALUE)
+}
+
+/// Format 32 steps as four-step groups separated by spaces.
+pub fn format_steps(steps: &[u8]) -> String {
+    let mut row = String::new();
+    for (i, step) in steps.iter().enumerate() {
+        if i > 0 && i % 4 == 0 {
+            row.push(' ');
+        }
+        row.push(step_char(*step));
+    }
+    row
+}
+
+fn text_error(line: usize, message: impl Into<String>) -> DrumGenError {
+    DrumGenError::InvalidText { line, message: message.into() }
+}
+
+// Step values of one line, ignoring whitespace. Errors carry `line_number`.
+pub(crate) fn parse_steps(line_number: usize, steps: &str) -> Result<Vec<u8>, DrumGenError> {
+    steps
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| parse_step_char(c).ok_or_else(|| text_error(line_number, format!("invalid step `{}` (expected 0-9 or A)", c))))
+        .collect()
+}
+
+impl DrumGen {
+    /// Write the template in the plain-text format, with an optional name header.
+    pub fn to_text(&self, name: Option<&str>) -> String {
+        let mut text = format!("{}\n", HEADER);
+        if let Some(name) = name {
+            text += &format!("{} {}\n", NAME_KEY, name);
+        }
+        for (p, part) in self.parts.iter().enumerate() {
+            text += "\n";
+            for (sequence, velocity) in part.sequence.iter().zip(LAYER_VELOCITIES) {
+                let label = format!("{}:{}", p + 1, velocity);
+                text += &format!("{:<5} | {}\n", label, format_steps(&sequence.steps));
+            }
+        }
+        text
+    }
+
+    /// Parse a template written by [`to_text`](Self::to_text).
+    ///
+    /// Returns the template and the name header, if any. Every part / velocity
+    /// line must be present exactly once.
+    pub fn from_text(text: &str) -> Result<(DrumGen, Option<String>), DrumGenError> {
+        let mut template = DrumGen::new();
+        let mut name: Option<String> = None;
+        let mut seen = [[false; 3]; 4];
+
+        for (index, raw_line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(value) = line.strip_prefix(NAME_KEY) {
+                if name.is_some() {
+                    return Err(text_error(line_number, "duplicate name header"));
+                }
+                name = Some(value.trim().to_string());
+                continue;
+            }
+
+            let (label, steps) = line
+                .split_once('|')
+                .ok_or_else(|| text_error(line_number, "expected `<part>:<velocity> | <steps>`"))?;
+            let (part, velocity) = label
+                .trim()
+                .split_once(':')
+                .ok_or_else(|| text_error(line_number, format!("invalid label `{}`", label.trim())))?;
+            let part = match part.trim().parse::<usize>() {
+                Ok(part @ 1..=4) => part - 1,
+                _ => return Err(text_error(line_number, format!("invalid part `{}` (expected 1-4)", part.trim()))),
+            };
+            let layer = velocity
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .and_then(|velocity| LAYER_VELOCITIES.iter().position(|v| *v == velocity))
+                .ok_or_else(|| {
+                    text_error(line_number, format!("invalid velocity `{}` (expected 127, 100 or 60)", velocity.trim()))
+                })?;
+            if seen[part][layer] {
+                return Err(text_error(line_number, format!("duplicate line for {}", label.trim())));
+            }
+            seen[part][layer] = true;
+
+            let values = parse_steps(line_number, steps)?;
+            let sequence = &mut template.parts[part].sequence[layer];
+            if values.len() != sequence.steps.len() {
+                return Err(text_error(line_number, format!("expected 32 steps, got {}", values.len())));
+            }
+            sequence.steps.copy_from_slice(&values);
+        }
+
+        for (part, layers) in seen.iter().enumerate() {
+            for (layer, found) in layers.iter().enumerate() {
+                if !found {
+                    return Err(text_error(
+                        text.lines().count(),
+                        format!("missing line for {}:{}", part + 1, LAYER_VELOCITIES[layer]),
+                    ));
+                }
+            }
+        }
+        Ok((template, name))
+    }
+}
+
+/// Steps copied from one or more neighbouring velocity layers of a part.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepClip {
+    /// Index of the first copied layer (0 = 127).
+    pub first_layer: usize,
+    /// One row of step values per layer, all the same length.
+    pub rows: Vec<Vec<u8>>,
+}
+
+impl StepClip {
+    /// Copy a range of steps from a range of layers of a part.
+    /// Layers and steps past the end of the part are left out.
+    pub fn copy(part: &DrumGenLayer, layers: Range<usize>, steps: Range<usize>) -> StepClip {
+        let layer_count = part.sequence.len();
+        let end = layers.end.min(layer_count);
+        let layers = layers.start.min(end)..end;
+        let steps = clamp_steps(steps);
+        StepClip {
+            first_layer: layers.start,
+            rows: part.sequence[layers].iter().map(|sequence| sequence.steps[steps.clone()].to_vec()).collect(),
+        }
+    }
+
+    /// Number of steps in each row.
+    pub fn len(&self) -> usize {
+        self.rows.first().map_or(0, Vec::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Paste into a part, starting at `first_layer` and filling the step range.
+    ///
+    /// A clip shorter than the range is repeated until the range is full.
+    /// Rows that would land below the last layer are dropped, the range is cut
+    /// off at the last step and locked steps are left alone.
+    pub fn paste(&self, part: &mut DrumGenLayer, first_layer: usize, steps: Range<usize>) {
+        let steps = clamp_steps(steps);
+        for (row, sequence) in self.rows.iter().zip(part.sequence.iter_mut().skip(first_layer)) {
+            if row.is_empty() {
+                continue;
+            }
+            for (offset, step) in steps.clone().enumerate() {
+                sequence.set_unlocked(step, row[offset % row.len()]);
+            }
+        }
+    }
+
+    /// Write the clip in the text format.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", CLIP_HEADER);
+        for (layer, row) in self.rows.iter().enumerate() {
+            let velocity = LAYER_VELOCITIES.get(self.first_layer + layer).copied().unwrap_or_default();
+            text += &format!("{:<5} | {}\n", velocity, format_steps(row));
+        }
+        text
+    }
+
+    /// Parse a clip written by [`to_text`](Self::to_text).
+    ///
+    /// The rows must belong to neighbouring layers and have the same number of steps (1-32).
+    pub fn from_text(text: &str) -> Result<StepClip, DrumGenError> {
+        let mut layers = Vec::new();
+        let mut rows: Vec<Vec<u8>> = Vec::new();
+        for (index, raw_line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (velocity, steps) = line
+                .split_once('|')
+                .ok_or_else(|| text_error(line_number, "expected `<velocity> | <steps>`"))?;
+            let layer = velocity
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .and_then(|velocity| LAYER_VELOCITIES.iter().position(|v| *v == velocity))
+                .ok_or_else(|| {
+                    text_error(line_number, format!("invalid velocity `{}` (expected 127, 100 or 60)", velocity.trim()))
+                })?;
+            if layers.last().is_some_and(|last| layer != last + 1) {
+                return Err(text_error(line_number, "layers must follow each other (127, 100, 60)"));
+            }
+            let row = parse_steps(line_number, steps)?;
+            if row.is_empty() || row.len() > STEP_COUNT {
+                return Err(text_error(line_number, format!("expected 1-32 steps, got {}", row.len())));
+            }
+            if rows.first().is_some_and(|first| first.len() != row.len()) {
+                return Err(text_error(line_number, "every layer must have the same number of steps"));
+            }
+            layers.push(layer);
+            rows.push(row);
+        }
+        match layers.first() {
+            Some(first_layer) => Ok(StepClip { first_layer: *first_layer, rows }),
+            None => Err(text_error(text.lines().count(), "no steps found")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drumgen::StepMask;
+    use rand::prelude::*;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn text_round_trips_random_templates() {
+        let mut rng = StdRng::seed_from_u64(0x7E47);
+        for _ in 0..100 {
+            let mut template = DrumGen::new();
+            for part in template.parts.iter_mut() {
+                for sequence in part.sequence.iter_mut() {
+                    for step in sequence.steps.iter_mut() {
+                        *step = rng.gen_range(0..=MAX_STEP_VALUE);
+                    }
+                }
+            }
+            let (decoded, name) = DrumGen::from_text(&template.to_text(Some("DGT_0001"))).unwrap();
+            assert_eq!(decoded, template);
+            assert_eq!(name.as_deref(), Some("DGT_0001"));
+
+            let (decoded, name) = DrumGen::from_text(&template.to_text(None)).unwrap();
+            assert_eq!(decoded, template);
+            assert_eq!(name, None);
+        }
+    }
+
+    #[test]
+    fn text_layout() {
+        let mut template = DrumGen::new();
+        template.parts[0].sequence[0].set_step(0, 10);
+        template.parts[3].sequence[2].set_step(31, 7);
+        let text = template.to_text(Some("KICKS"));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], HEADER);
+        assert_eq!(lines[1], "name: KICKS");
+        assert_eq!(lines[3], "1:127 | A000 0000 0000 0000 0000 0000 0000 0000");
+        assert_eq!(lines[5], "1:60  | 0000 0000 0000 0000 0000 0000 0000 0000");
+        assert_eq!(*lines.last().unwrap(), "4:60  | 0000 0000 0000 0000 0000 0000 0000 0007");
+    }
+
+    #[test]
+    fn from_text_accepts_any_order_and_spacing() {
+        let mut text = String::new();
+        for part in (1..=4).rev() {
+            for velocity in LAYER_VELOCITIES {
+                text += &format!("  {}:{}|{}\n", part, velocity, "a".repeat(32));
+            }
+        }
+        let (template, _) = DrumGen::from_text(&text).unwrap();
+        assert!(template.parts.iter().all(|part| part.sequence.iter().all(|s| s.steps == [10; 32])));
+    }
+
+    #[test]
+    fn from_text_reports_errors_with_line_numbers() {
+        let valid = DrumGen::new().to_text(None);
+        let replace_line = |line: usize, with: &str| {
+            let mut lines: Vec<String> = valid.lines().map(String::from).collect();
+            lines[line - 1] = with.to_string();
+            lines.join("\n")
+        };
+
+        let cases = [
+            (replace_line(3, "1:127 | 0000"), 3, "expected 32 steps, got 4"),
+            (replace_line(3, "1:127 | B000 0000 0000 0000 0000 0000 0000 0000"), 3, "invalid step `B`"),
+            (replace_line(3, "5:127 | 0000 0000 0000 0000 0000 0000 0000 0000"), 3, "invalid part `5`"),
+            (replace_line(4, "1:90 | 0000 0000 0000 0000 0000 0000 0000 0000"), 4, "invalid velocity `90`"),
+            (replace_line(4, "1:127 | 0000 0000 0000 0000 0000 0000 0000 0000"), 4, "duplicate line for 1:127"),
+            (replace_line(4, "hello"), 4, "expected `<part>:<velocity> | <steps>`"),
+        ];
+        for (text, expected_line, expected_message) in cases {
+            match DrumGen::from_text(&text) {
+                Err(DrumGenError::InvalidText { line, message }) => {
+                    assert_eq!(line, expected_line);
+                    assert!(message.contains(expected_message), "{}", message);
+                }
+                other => panic!("expected a text error, got {:?}", other),
+            }
+        }
+
+        match DrumGen::from_text(&replace_line(17, "")) {
+            Err(DrumGenError::InvalidText { message, .. }) => assert_eq!(message, "missing line for 4:60"),
+            other => panic!("expected a text error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn clip_round_trips_through_text() {
+        let mut part = DrumGenLayer::new();
+        part.sequence[1].steps[4..8].copy_from_slice(&[10, 0, 5, 1]);
+        part.sequence[2].steps[4..8].copy_from_slice(&[0, 3, 0, 3]);
+        let clip = StepClip::copy(&part, 1..3, 4..8);
+        let text = clip.to_text();
+        assert_eq!(text, "# DrumGen steps\n100   | A051\n60    | 0303\n");
+        assert_eq!(StepClip::from_text(&text).unwrap(), clip);
+    }
+
+    #[test]
+    fn paste_tiles_short_clips() {
+        let clip = StepClip { first_layer: 0, rows: vec![vec![10, 0, 0]] };
+        let mut part = DrumGenLayer::new();
+        clip.paste(&mut part, 2, 0..8);
+        assert_eq!(&part.sequence[2].steps[..9], &[10, 0, 0, 10, 0, 0, 10, 0, 0]);
+        assert!(part.sequence[0].steps.iter().all(|step| *step == 0));
+    }
+
+    #[test]
+    fn paste_skips_locked_steps_and_stops_at_the_last_step() {
+        let clip = StepClip { first_layer: 0, rows: vec![vec![5]] };
+        let mut part = DrumGenLayer::new();
+        part.sequence[0].set_locked(StepMask(1 << 30));
+        clip.paste(&mut part, 0, 28..40);
+        assert_eq!(&part.sequence[0].steps[27..], &[0, 5, 5, 0, 5]);
+        clip.paste(&mut part, 0, 40..50);
+        assert_eq!(&part.sequence[0].steps[27..], &[0, 5, 5, 0, 5]);
+    }
+
+    #[test]
+    fn copy_leaves_out_what_is_past_the_end() {
+        let mut part = DrumGenLayer::new();
+        part.sequence[2].steps[30..].copy_from_slice(&[4, 5]);
+        let clip = StepClip::copy(&part, 2..9, 30..40);
+        assert_eq!(clip, StepClip { first_layer: 2, rows: vec![vec![4, 5]] });
+        assert!(StepClip::copy(&part, 0..3, 40..50).is_empty());
+    }
+
+    #[test]
+    fn paste_drops_rows_below_the_last_layer() {
+        let mut source = DrumGenLayer::new();
+        for sequence in source.sequence.iter_mut() {
+            sequence.steps = [7; 32];
+        }
+        let clip = StepClip::copy(&source, 0..3, 0..32);
+        let mut part = DrumGenLayer::new();
+        clip.paste(&mut part, 1, 0..32);
+        assert_eq!(part.sequence[0].steps, [0; 32]);
+        assert_eq!(part.sequence[1].steps, [7; 32]);
+        assert_eq!(part.sequence[2].steps, [7; 32]);
+    }
+
+    #[test]
+    fn clip_from_text_rejects_bad_input() {
+        assert!(StepClip::from_text("").is_err());
+        assert!(StepClip::from_text("127 | A0\n60 | 00").is_err());
+        assert!(StepClip::from_text("127 | A0\n100 | 000").is_err());
+        assert!(StepClip::from_text(&format!("127 | {}", "0".repeat(33))).is_err());
+        assert!(StepClip::from_text("90 | A0").is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use drumgen::DrumGen;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn cli(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_drumgen-cli"))
+        .args(args)
+        .output()
+        .expect("failed to run drumgen-cli")
+}
+
+// Fresh scratch directory holding a copy of the fixtures.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("drumgen_cli_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for fixture in ["empty.hex", "four_on_floor.hex"] {
+        let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(fixture);
+        fs::copy(source, dir.join(fixture)).unwrap();
+    }
+    dir
+}
+
+fn load(path: &Path) -> DrumGen {
+    let mut template = DrumGen::new();
+    template.parse_file(path).unwrap();
+    template
+}
+
+#[test]
+fn validate_fails_on_invalid_files() {
+    let dir = scratch_dir("validate");
+    fs::write(dir.join("bad.hex"), [0u8; 10]).unwrap();
+
+    let pattern = dir.join("*.hex").display().to_string();
+    let output = cli(&["validate", &pattern]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("four_on_floor.hex: OK, but NGEN won't load it"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("expected 192 bytes, got 10"));
+
+    fs::remove_file(dir.join("bad.hex")).unwrap();
+    assert!(cli(&["validate", &pattern]).status.success());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn shift_and_clear_edit_selected_sequence() {
+    let dir = scratch_dir("shift");
+    let file = dir.join("four_on_floor.hex");
+    let shifted = dir.join("shifted.hex");
+
+    let output = cli(&["shift", "--by", "-1", "--part", "1", "--layer", "1", file.to_str().unwrap(), "-o", shifted.to_str().unwrap()]);
+    assert!(output.status.success());
+    let original = load(&file);
+    let template = load(&shifted);
+    assert_eq!(template.parts[0].sequence[0].steps[..5], [0, 10, 0, 0, 0]);
+    assert_eq!(template.parts[1], original.parts[1]);
+
+    assert!(cli(&["clear", "--part", "3", file.to_str().unwrap()]).status.success());
+    let cleared = load(&file);
+    assert_eq!(cleared.parts[2], DrumGen::new().parts[2]);
+    assert_eq!(cleared.parts[0], original.parts[0]);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn copy_part_from_another_template() {
+    let dir = scratch_dir("copy");
+    let source = dir.join("four_on_floor.hex");
+    let file = dir.join("empty.hex");
+
+    let output = cli(&["copy-part", "--from", "2", "--to", "4", "--source", source.to_str().unwrap(), file.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(load(&file).parts[3], load(&source).parts[1]);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn randomize_repeats_with_the_same_seed() {
+    let dir = scratch_dir("randomize");
+    let file = dir.join("empty.hex");
+    let randomized = |seed: &str, name: &str| {
+        let output_file = dir.join(name);
+        let output = cli(&["randomize", "--seed", seed, "--part", "2", file.to_str().unwrap(), "-o", output_file.to_str().unwrap()]);
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).contains(&format!("seed {}", seed)));
+        load(&output_file)
+    };
+    let first = randomized("11", "a.hex");
+    assert_eq!(first, randomized("11", "b.hex"));
+    assert_ne!(first, randomized("12", "c.hex"));
+    assert_eq!(first.parts[0], load(&file).parts[0]);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn output_requires_single_input() {
+    let dir = scratch_dir("output");
+    let pattern = dir.join("*.hex").display().to_string();
+    let output = cli(&["clear", &pattern, "-o", dir.join("out.hex").to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(!dir.join("out.hex").exists());
+    let _ = fs::remove_dir_all(&dir);
+}